@@ -0,0 +1,57 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    fmt::Write,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+};
+
+use sass_rs::{compile_file, Options, OutputStyle};
+
+/// Compile style sheet, named by hash of its content so it could be cached forever, and list it
+/// along with everything under `static/` for embedding into the binary.
+fn main() {
+    println!("cargo:rerun-if-changed=assets");
+    println!("cargo:rerun-if-changed=static");
+
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR").unwrap());
+
+    let style_css = compile_file(
+        "assets/main.sass",
+        Options {
+            output_style: OutputStyle::Compressed,
+            ..Default::default()
+        },
+    )
+    .expect("failed to compile style sheet");
+
+    let mut hasher = DefaultHasher::new();
+    style_css.hash(&mut hasher);
+    let style_name = format!("style-{:08x}.css", hasher.finish() as u32);
+    let style_path = out_dir.join(&style_name);
+    std::fs::write(&style_path, style_css).expect("failed to write style sheet");
+
+    let mut files = vec![(style_name.clone(), style_path)];
+    collect_files(Path::new("static"), "", &mut files);
+
+    let mut code = String::from("pub static FILES: &[(&str, &[u8])] = &[\n");
+    for (name, path) in files {
+        let path = path.canonicalize().expect("failed to resolve static file");
+        writeln!(code, "    ({:?}, include_bytes!({:?})),", name, path).unwrap();
+    }
+    code.push_str("];\n");
+    std::fs::write(out_dir.join("static_files.rs"), code).expect("failed to write file list");
+
+    println!("cargo:rustc-env=STYLE_CSS={}", style_name);
+}
+
+fn collect_files(dir: &Path, prefix: &str, files: &mut Vec<(String, PathBuf)>) {
+    for entry in std::fs::read_dir(dir).expect("failed to read static directory") {
+        let path = entry.expect("failed to read static directory").path();
+        let name = format!("{}{}", prefix, path.file_name().unwrap().to_string_lossy());
+        if path.is_dir() {
+            collect_files(&path, &format!("{}/", name), files);
+        } else {
+            files.push((name, path));
+        }
+    }
+}
@@ -1,23 +1,127 @@
 use std::{
-    collections::VecDeque,
-    path::PathBuf,
-    time::{Duration, Instant},
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+    future::Future,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
-use futures::lock::Mutex;
+use chrono::{DateTime, Utc};
+use futures::{
+    future::{BoxFuture, FutureExt, Shared},
+    lock::Mutex,
+    stream::{FuturesUnordered, StreamExt},
+};
 use indexmap::map::IndexMap;
-use lru::LruCache;
 use once_cell::sync::Lazy;
 use semver::{Version, VersionReq};
+use sled::Tree;
 
 use crate::{
-    database::{CrateMeta, DependencyKind},
-    model::{RepoIdentity, Status},
-    parser::{Dependency, Manifest},
-    utils::AnyResult,
+    database::{AdvisoryRecord, CrateMeta, DependencyKind},
+    model::{CrateIdentity, CrateSpec, Lag, RepoIdentity, Status, VersionSpec},
+    parser::{Dependency, Manifest, Policy, RepoConfig},
 };
 
-#[derive(Debug, Deserialize, Clone)]
+/// Why a repo couldn't be analyzed.
+#[derive(Debug, Clone)]
+pub enum AnalyzeError {
+    /// no such repo, file, crate or version
+    NotFound(String),
+    /// request itself is malformed, e.g. ref or path escaping the repo
+    Invalid(String),
+    /// a manifest or config file is malformed
+    Parse {
+        path: PathBuf,
+        line: Option<usize>,
+        message: String,
+    },
+    /// upstream host failed or timed out
+    Upstream(String),
+    /// analysis as a whole took longer than `--analysis-timeout`
+    Timeout,
+    /// a blocking task panicked or was canceled
+    Internal(String),
+}
+
+impl AnalyzeError {
+    fn from_fetch(path: &Path, error: anyhow::Error) -> Self {
+        if crate::fetch::is_not_found(&error) {
+            AnalyzeError::NotFound(format!("{} not found", path.display()))
+        } else {
+            AnalyzeError::Upstream(format!("failed to fetch {}: {}", path.display(), error))
+        }
+    }
+
+    fn from_toml(path: &Path, error: toml::de::Error) -> Self {
+        AnalyzeError::Parse {
+            path: path.to_path_buf(),
+            line: error.line_col().map(|(line, _)| line + 1),
+            message: error.to_string(),
+        }
+    }
+
+    /// Status shown on badges.
+    pub fn status(&self) -> Status {
+        match self {
+            AnalyzeError::NotFound(_) => Status::NotFound,
+            _ => Status::Error,
+        }
+    }
+}
+
+impl Display for AnalyzeError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            AnalyzeError::NotFound(message) => write!(f, "{}", message),
+            AnalyzeError::Invalid(message) => write!(f, "{}", message),
+            AnalyzeError::Parse {
+                path,
+                line: Some(line),
+                message,
+            } => write!(
+                f,
+                "failed to parse {} at line {}: {}",
+                path.display(),
+                line,
+                message
+            ),
+            AnalyzeError::Parse { path, message, .. } => {
+                write!(f, "failed to parse {}: {}", path.display(), message)
+            }
+            AnalyzeError::Upstream(message) => write!(f, "{}", message),
+            AnalyzeError::Timeout => write!(
+                f,
+                "analysis timed out after {}",
+                humantime::format_duration(crate::command::analysis_timeout())
+            ),
+            AnalyzeError::Internal(message) => write!(f, "internal error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for AnalyzeError {}
+
+/// Run sled reads and advisory queries on the blocking thread pool, off the async workers.
+pub async fn blocking<F, T>(f: F) -> Result<T, AnalyzeError>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    actix_web::web::block(move || Ok::<_, ()>(f()))
+        .await
+        .map_err(|e| AnalyzeError::Internal(format!("blocking task failed: {:?}", e)))
+}
+
+async fn with_timeout<T>(
+    future: impl Future<Output = Result<T, AnalyzeError>>,
+) -> Result<T, AnalyzeError> {
+    actix_rt::time::timeout(crate::command::analysis_timeout(), future)
+        .await
+        .unwrap_or(Err(AnalyzeError::Timeout))
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AnalyzedCrate {
     pub name: String,
     pub dependencies: Vec<AnalyzedDependency>,
@@ -26,39 +130,111 @@ pub struct AnalyzedCrate {
 }
 
 impl AnalyzedCrate {
-    pub fn status(&self) -> Status {
-        if self.dependencies.iter().any(|x| x.is_insecure())
-            || self.dev_dependencies.iter().any(|x| x.is_insecure())
-            || self.build_dependencies.iter().any(|x| x.is_insecure())
-        {
+    /// Same crate with dependencies of given kind only.
+    pub fn only(&self, kind: DependencyKind) -> AnalyzedCrate {
+        let pick = |deps: &Vec<AnalyzedDependency>, this: DependencyKind| {
+            if this == kind {
+                deps.clone()
+            } else {
+                vec![]
+            }
+        };
+        AnalyzedCrate {
+            name: self.name.clone(),
+            dependencies: pick(&self.dependencies, DependencyKind::Normal),
+            dev_dependencies: pick(&self.dev_dependencies, DependencyKind::Dev),
+            build_dependencies: pick(&self.build_dependencies, DependencyKind::Build),
+        }
+    }
+
+    pub fn status(&self, policy: &Policy) -> Status {
+        let all = self
+            .dependencies
+            .iter()
+            .map(|x| (DependencyKind::Normal, x))
+            .chain(
+                self.dev_dependencies
+                    .iter()
+                    .map(|x| (DependencyKind::Dev, x)),
+            )
+            .chain(
+                self.build_dependencies
+                    .iter()
+                    .map(|x| (DependencyKind::Build, x)),
+            );
+
+        if all.clone().any(|(_, x)| x.is_insecure()) {
             return Status::Insecure;
         }
 
-        let total =
-            self.dependencies.len() + self.dev_dependencies.len() + self.build_dependencies.len();
-        let total = total as u32;
-        let outdated = self.dependencies.iter().filter(|x| x.is_outdated()).count()
-            + self
-                .dev_dependencies
-                .iter()
-                .filter(|x| x.is_outdated())
-                .count()
-            + self
-                .build_dependencies
-                .iter()
-                .filter(|x| x.is_outdated())
-                .count();
-        let outdated = outdated as u32;
-        Status::Normal { total, outdated }
+        let counted = all
+            .filter(|(kind, _)| policy.counts_kind(*kind))
+            .map(|(_, x)| x)
+            .collect::<Vec<_>>();
+        let total = counted.len() as u32;
+        let outdated = counted
+            .iter()
+            .filter(|x| x.is_outdated_under(policy))
+            .collect::<Vec<_>>();
+        let lag = outdated
+            .iter()
+            .map(|x| x.lag())
+            .max()
+            .unwrap_or(Lag::UpToDate);
+        let outdated = outdated.len() as u32;
+        Status::Normal {
+            total,
+            outdated,
+            lag,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AnalyzedRepo {
+    pub config: RepoConfig,
+    pub crates: Vec<AnalyzedCrate>,
+    /// workspace members whose manifest is missing or malformed
+    #[serde(default)]
+    pub failed_members: Vec<FailedMember>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FailedMember {
+    /// directory of member, relative to repo root
+    pub path: String,
+    pub error: String,
+}
+
+impl AnalyzedRepo {
+    /// Same repo with dependencies of given kind only.
+    pub fn only(&self, kind: DependencyKind) -> AnalyzedRepo {
+        AnalyzedRepo {
+            config: self.config.clone(),
+            crates: self.crates.iter().map(|x| x.only(kind)).collect(),
+            failed_members: self.failed_members.clone(),
+        }
+    }
+
+    pub fn status(&self) -> Status {
+        self.crates
+            .iter()
+            .map(|x| x.status(&self.config.policy))
+            .sum()
     }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AnalyzedDependency {
     pub name: String,
     pub required: VersionReq,
     pub latest_that_matches: Option<Version>,
     pub latest: Option<Version>,
+    /// when `latest` first appeared in the index, if known
+    pub latest_released_at: Option<DateTime<Utc>>,
+    pub advisories: Vec<AdvisoryRecord>,
+    /// advisories accepted by repo config, not counted in status
+    pub ignored_advisories: Vec<AdvisoryRecord>,
 }
 
 impl AnalyzedDependency {
@@ -66,24 +242,40 @@ impl AnalyzedDependency {
         self.latest > self.latest_that_matches
     }
 
-    pub fn is_insecure(&self) -> bool {
-        if self.latest_that_matches.is_none() {
-            return false;
+    pub fn lag(&self) -> Lag {
+        let latest = match &self.latest {
+            None => return Lag::UpToDate,
+            Some(x) => x,
+        };
+        match &self.latest_that_matches {
+            Some(current) => Lag::between(current, latest),
+            None => Lag::Unmatched,
         }
+    }
 
-        let version = self.latest_that_matches.as_ref().unwrap().clone();
-        match crate::database::is_insecure(&self.name, version) {
-            Ok(x) => x,
-            Err(error) => {
-                error!("failed to query audit database: {:?}", error);
-                false
-            }
-        }
+    /// Latest release is older than configured `--stale-after`.
+    pub fn is_stale(&self) -> bool {
+        let age = match self.latest_released_at {
+            None => return false,
+            Some(x) => Utc::now() - x,
+        };
+        age.to_std()
+            .map_or(false, |x| x > crate::command::stale_after())
+    }
+
+    /// Outdated and not accepted by given policy.
+    pub fn is_outdated_under(&self, policy: &Policy) -> bool {
+        self.is_outdated() && !policy.tolerates(&self.name, self.latest_that_matches.as_ref())
+    }
+
+    pub fn is_insecure(&self) -> bool {
+        !self.advisories.is_empty()
     }
 }
 
 fn analyze_dependencies(
     input: IndexMap<String, crate::parser::Dependency>,
+    config: &RepoConfig,
 ) -> Vec<AnalyzedDependency> {
     let mut result = vec![];
 
@@ -117,17 +309,75 @@ fn analyze_dependencies(
             .filter(|x| required.matches(x))
             .max();
 
+        let latest_released_at = match &latest {
+            None => None,
+            Some(version) => match crate::database::get_release_time(&name, version) {
+                Ok(x) => x,
+                Err(error) => {
+                    error!("failed to get release time: {:?}", error);
+                    None
+                }
+            },
+        };
+
+        let advisories = match &latest_that_matches {
+            None => vec![],
+            Some(version) => match crate::database::find_advisories(&name, version.clone()) {
+                Ok(x) => x,
+                Err(error) => {
+                    error!("failed to query audit database: {:?}", error);
+                    vec![]
+                }
+            },
+        };
+        let (ignored_advisories, advisories) = advisories
+            .into_iter()
+            .partition(|x| config.advisories.is_ignored(&name, &x.id));
+
         result.push(AnalyzedDependency {
             name,
             required,
             latest_that_matches,
             latest,
+            latest_released_at,
+            advisories,
+            ignored_advisories,
         });
     }
 
     result
 }
 
+/// Resolve the version given spec refers to, `None` if no such crate or nothing matches.
+pub fn resolve_crate(spec: &CrateSpec) -> Option<CrateIdentity> {
+    let version_spec = spec.version_spec()?;
+    if let VersionSpec::Exact(version) = version_spec {
+        return Some(CrateIdentity {
+            name: spec.name.clone(),
+            version,
+        });
+    }
+
+    let metas = match crate::database::get_crate_metas(&spec.name) {
+        Ok(x) => x?,
+        Err(error) => {
+            error!("failed to get crate metadata: {:?}", error);
+            return None;
+        }
+    };
+    let candidates = metas.into_iter().filter(|x| !x.yanked).map(|x| x.vers);
+    let version = match version_spec {
+        VersionSpec::Latest => candidates.filter(|x| !x.is_prerelease()).max(),
+        VersionSpec::Req(req) => candidates.filter(|x| req.matches(x)).max(),
+        VersionSpec::Exact(_) => unreachable!(),
+    }?;
+
+    Some(CrateIdentity {
+        name: spec.name.clone(),
+        version,
+    })
+}
+
 pub fn analyze_crate(crate_name: &str, version: Version) -> Option<AnalyzedCrate> {
     let meta: CrateMeta = crate::database::get_crate_metas(crate_name)
         .ok()??
@@ -154,58 +404,326 @@ pub fn analyze_crate(crate_name: &str, version: Version) -> Option<AnalyzedCrate
         .cloned()
         .map(|x| (x.name, crate::parser::Dependency::Direct(x.req)))
         .collect();
+    let config = RepoConfig::default();
     let result = AnalyzedCrate {
         name: crate_name.to_string(),
-        dependencies: analyze_dependencies(dependencies),
-        dev_dependencies: analyze_dependencies(dev_dependencies),
-        build_dependencies: analyze_dependencies(build_dependencies),
+        dependencies: analyze_dependencies(dependencies, &config),
+        dev_dependencies: analyze_dependencies(dev_dependencies, &config),
+        build_dependencies: analyze_dependencies(build_dependencies, &config),
     };
     Some(result)
 }
 
+/// Resolve and analyze crate on the blocking pool, `None` if no such crate or version.
+pub async fn analyze_crate_spec(
+    spec: &CrateSpec,
+) -> Result<Option<(CrateIdentity, AnalyzedCrate)>, AnalyzeError> {
+    let spec = spec.clone();
+    with_timeout(blocking(move || {
+        let ident = resolve_crate(&spec)?;
+        let analyzed = analyze_crate(&ident.name, ident.version.clone())?;
+        Some((ident, analyzed))
+    }))
+    .await
+}
+
+type AnalyzeResult = Result<AnalyzedRepo, AnalyzeError>;
+
+/// `RepoIdentity` path and query -> `CachedAnalysis` in JSON
+static ANALYSIS_DB: Lazy<Tree> =
+    Lazy::new(|| crate::command::database().open_tree("analysis").unwrap());
+/// approximate number of entries in `ANALYSIS_DB`
+static ANALYSIS_COUNT: Lazy<AtomicUsize> = Lazy::new(|| AtomicUsize::new(ANALYSIS_DB.len()));
+
+/// Analyses not finished yet, callers of the same repo await the same one.
 #[allow(clippy::type_complexity)]
-pub async fn analyze_repo(identity: &RepoIdentity) -> AnyResult<Vec<AnalyzedCrate>> {
-    static CACHE: Lazy<Mutex<LruCache<RepoIdentity, (Instant, Vec<AnalyzedCrate>)>>> =
-        Lazy::new(|| Mutex::new(LruCache::new(1024)));
-
-    {
-        if let Some((created_at, cache)) = CACHE.lock().await.get(identity).cloned() {
-            let created_at: Instant = created_at;
-            if created_at.elapsed() < Duration::from_secs(60) {
-                trace!("cache hint: {:?}", identity);
-                return Ok(cache);
-            }
+static IN_FLIGHT: Lazy<Mutex<HashMap<RepoIdentity, Shared<BoxFuture<'static, AnalyzeResult>>>>> =
+    Lazy::new(Default::default);
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedAnalysis {
+    created_at: DateTime<Utc>,
+    /// index tree the analysis was based on
+    tree_id: Option<String>,
+    repo: AnalyzedRepo,
+}
+
+/// Analyze repo, an expired result is returned at once while a fresh one is computed in
+/// background.
+pub async fn analyze_repo(identity: &RepoIdentity) -> AnalyzeResult {
+    let key = identity.clone();
+    match blocking(move || get_cached_analysis(&key)).await? {
+        Some((repo, true)) => {
+            trace!("cache hint: {:?}", identity);
+            crate::metrics::ANALYZE_CACHE.hit();
+            return Ok(repo);
+        }
+        Some((repo, false)) => {
+            trace!("stale cache hint: {:?}", identity);
+            crate::metrics::ANALYZE_CACHE.hit();
+            let identity = identity.clone();
+            actix_rt::spawn(async move {
+                if let Err(e) = analyze_repo_shared(&identity).await {
+                    debug!("failed to revalidate {:?}: {}", identity, e);
+                }
+            });
+            return Ok(repo);
         }
+        None => crate::metrics::ANALYZE_CACHE.miss(),
     }
 
-    let mut result = vec![];
-    let mut rel_paths = VecDeque::new();
+    analyze_repo_shared(identity).await
+}
 
-    rel_paths.push_back(PathBuf::from(""));
-    while let Some(rel_path) = rel_paths.pop_front() {
-        let content = crate::fetch::fetch(identity, &rel_path.join("Cargo.toml")).await?;
-        let manifest: Manifest = toml::from_slice(content.as_ref())?;
+async fn analyze_repo_shared(identity: &RepoIdentity) -> AnalyzeResult {
+    let analysis = {
+        let mut in_flight = IN_FLIGHT.lock().await;
+        match in_flight.get(identity) {
+            Some(x) => {
+                trace!("joining in-flight analysis: {:?}", identity);
+                x.clone()
+            }
+            None => {
+                let key = identity.clone();
+                let analysis = async move {
+                    let result = with_timeout(analyze_repo_uncached(&key)).await;
+                    let target = key.clone();
+                    let cached = match &result {
+                        Ok(x) => {
+                            let x = x.clone();
+                            blocking(move || put_cached_analysis(&target, &x)).await
+                        }
+                        // a stale result of a repo that's gone or broken is not served any more,
+                        // transient failures keep it till `--analysis-max-stale`
+                        Err(AnalyzeError::NotFound(_))
+                        | Err(AnalyzeError::Parse { .. })
+                        | Err(AnalyzeError::Invalid(_)) => {
+                            blocking(move || remove_cached_analysis(&target)).await
+                        }
+                        Err(_) => Ok(()),
+                    };
+                    if let Err(e) = cached {
+                        error!("failed to update analysis cache: {}", e);
+                    }
+                    IN_FLIGHT.lock().await.remove(&key);
+                    result
+                }
+                .boxed()
+                .shared();
+                in_flight.insert(identity.clone(), analysis.clone());
+                analysis
+            }
+        }
+    };
 
-        if let Some(package) = manifest.package {
-            result.push(AnalyzedCrate {
-                name: package.name,
-                dependencies: analyze_dependencies(manifest.dependencies),
-                dev_dependencies: analyze_dependencies(manifest.dev_dependencies),
-                build_dependencies: analyze_dependencies(manifest.build_dependencies),
+    analysis.await
+}
+
+fn cache_key(identity: &RepoIdentity) -> String {
+    format!("{}{}", identity.path(), identity.query())
+}
+
+/// Cached analysis and whether it's still fresh, it's stale once expired, and not served at all
+/// once based on another index or older than `--analysis-max-stale`.
+fn get_cached_analysis(identity: &RepoIdentity) -> Option<(AnalyzedRepo, bool)> {
+    let value = match ANALYSIS_DB.get(cache_key(identity)) {
+        Ok(x) => x?,
+        Err(e) => {
+            error!("failed to read analysis cache: {:?}", e);
+            return None;
+        }
+    };
+    let cached: CachedAnalysis = match serde_json::from_slice(&value) {
+        Ok(x) => x,
+        Err(e) => {
+            warn!("broken analysis cache of {:?}: {}", identity, e);
+            return None;
+        }
+    };
+
+    let tree_id = crate::database::index_tree_id();
+    if cached.tree_id != tree_id.map(|x| x.to_string()) {
+        trace!("index changed since analysis of {:?}", identity);
+        return None;
+    }
+    let age = (Utc::now() - cached.created_at)
+        .to_std()
+        .unwrap_or_default();
+    if age >= crate::command::analysis_max_stale() {
+        return None;
+    }
+    Some((cached.repo, age < crate::command::analysis_ttl()))
+}
+
+fn remove_cached_analysis(identity: &RepoIdentity) {
+    match ANALYSIS_DB.remove(cache_key(identity)) {
+        Ok(Some(_)) => {
+            let _ = ANALYSIS_COUNT.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |x| {
+                Some(x.saturating_sub(1))
             });
         }
-        for i in manifest.workspace.members {
-            let rel_path = rel_path.join(i);
-            rel_paths.push_back(rel_path);
+        Ok(None) => {}
+        Err(e) => error!("failed to remove analysis cache: {:?}", e),
+    }
+}
+
+fn put_cached_analysis(identity: &RepoIdentity, repo: &AnalyzedRepo) {
+    let cached = CachedAnalysis {
+        created_at: Utc::now(),
+        tree_id: crate::database::index_tree_id().map(|x| x.to_string()),
+        repo: repo.clone(),
+    };
+    let value = match serde_json::to_vec(&cached) {
+        Ok(x) => x,
+        Err(e) => {
+            error!("failed to serialize analysis of {:?}: {}", identity, e);
+            return;
+        }
+    };
+    match ANALYSIS_DB.insert(cache_key(identity), value) {
+        Ok(None) => {
+            let count = ANALYSIS_COUNT.fetch_add(1, Ordering::Relaxed) + 1;
+            if count > crate::command::analysis_capacity() {
+                evict_cached_analyses();
+            }
+        }
+        Ok(Some(_)) => {}
+        Err(e) => error!("failed to write analysis cache: {:?}", e),
+    }
+}
+
+/// Drop the oldest entries till only 90% of capacity is used.
+fn evict_cached_analyses() {
+    let mut entries = vec![];
+    for item in ANALYSIS_DB.iter() {
+        match item {
+            Ok((key, value)) => {
+                let created_at = serde_json::from_slice::<CachedAnalysis>(&value)
+                    .map(|x| x.created_at.timestamp())
+                    .unwrap_or(i64::min_value());
+                entries.push((created_at, key));
+            }
+            Err(e) => {
+                error!("failed to scan analysis cache: {:?}", e);
+                return;
+            }
+        }
+    }
+
+    let keep = crate::command::analysis_capacity() * 9 / 10;
+    entries.sort_by_key(|(created_at, _)| *created_at);
+    let evict = entries.len().saturating_sub(keep);
+    for (_, key) in &entries[..evict] {
+        if let Err(e) = ANALYSIS_DB.remove(key) {
+            error!("failed to evict analysis cache: {:?}", e);
+        }
+    }
+    ANALYSIS_COUNT.store(entries.len() - evict, Ordering::Relaxed);
+    debug!("evicted {} cached analyses", evict);
+}
+
+async fn analyze_repo_uncached(identity: &RepoIdentity) -> AnalyzeResult {
+    async fn fetch_manifest(
+        identity: &RepoIdentity,
+        order: usize,
+        rel_path: PathBuf,
+    ) -> (usize, PathBuf, Result<Manifest, AnalyzeError>) {
+        let path = rel_path.join("Cargo.toml");
+        let result = match crate::fetch::fetch(identity, &path).await {
+            Ok(content) => {
+                toml::from_slice(content.as_ref()).map_err(|e| AnalyzeError::from_toml(&path, e))
+            }
+            Err(e) => Err(AnalyzeError::from_fetch(&path, e)),
+        };
+        (order, rel_path, result)
+    }
+
+    let config = fetch_repo_config(identity).await?;
+    let mut crates = vec![];
+    let mut failed_members = vec![];
+    // error of the first failed member, reported if no crate is left to analyze
+    let mut first_error: Option<(usize, AnalyzeError)> = None;
+
+    // members are fetched concurrently, limited by `fetch` per host, `order` keeps them in
+    // the order of appearance
+    let mut next_order = 1;
+    let mut pending = FuturesUnordered::new();
+    pending.push(fetch_manifest(identity, 0, PathBuf::from("")));
+    while let Some((order, rel_path, result)) = pending.next().await {
+        let manifest = match result {
+            Ok(x) => x,
+            Err(e @ AnalyzeError::Upstream(_)) => return Err(e),
+            Err(e) if order == 0 => return Err(e),
+            Err(e) => {
+                failed_members.push((
+                    order,
+                    FailedMember {
+                        path: rel_path.display().to_string(),
+                        error: e.to_string(),
+                    },
+                ));
+                if first_error.as_ref().map_or(true, |(x, _)| order < *x) {
+                    first_error = Some((order, e));
+                }
+                continue;
+            }
+        };
+
+        let Manifest {
+            package,
+            workspace,
+            dependencies,
+            dev_dependencies,
+            build_dependencies,
+        } = manifest;
+        for i in workspace.members {
+            pending.push(fetch_manifest(identity, next_order, rel_path.join(i)));
+            next_order += 1;
+        }
+        if let Some(package) = package {
+            let config = config.clone();
+            let analyzed = blocking(move || AnalyzedCrate {
+                name: package.name,
+                dependencies: analyze_dependencies(dependencies, &config),
+                dev_dependencies: analyze_dependencies(dev_dependencies, &config),
+                build_dependencies: analyze_dependencies(build_dependencies, &config),
+            })
+            .await?;
+            crates.push((order, analyzed));
         }
     }
 
-    {
-        CACHE
-            .lock()
-            .await
-            .put(identity.clone(), (Instant::now(), result.clone()));
+    if crates.is_empty() {
+        return Err(match first_error {
+            Some((_, e)) => e,
+            None => AnalyzeError::NotFound(format!("no crate found in {}", identity.title())),
+        });
+    }
+
+    crates.sort_by_key(|(order, _)| *order);
+    failed_members.sort_by_key(|(order, _)| *order);
+    Ok(AnalyzedRepo {
+        config,
+        crates: crates.into_iter().map(|(_, x)| x).collect(),
+        failed_members: failed_members.into_iter().map(|(_, x)| x).collect(),
+    })
+}
+
+/// Read `.deps-rs.toml` from repo root, fall back to `deny.toml`, default config if neither exists.
+async fn fetch_repo_config(identity: &RepoIdentity) -> Result<RepoConfig, AnalyzeError> {
+    let path = Path::new(".deps-rs.toml");
+    let content = crate::fetch::fetch_optional(identity, path)
+        .await
+        .map_err(|e| AnalyzeError::from_fetch(path, e))?;
+    if let Some(content) = content {
+        return toml::from_slice(content.as_ref()).map_err(|e| AnalyzeError::from_toml(path, e));
     }
 
-    Ok(result)
+    let path = Path::new("deny.toml");
+    let content = crate::fetch::fetch_optional(identity, path)
+        .await
+        .map_err(|e| AnalyzeError::from_fetch(path, e))?;
+    Ok(content.map_or_else(RepoConfig::default, |x| {
+        RepoConfig::from_deny_toml(x.as_ref())
+    }))
 }
@@ -5,7 +5,10 @@ use sled::Db;
 use structopt::StructOpt;
 use url::Url;
 
-use crate::utils::AnyResult;
+use crate::{
+    logger::{LogFilter, LogFormat},
+    utils::AnyResult,
+};
 
 static COMMAND: Lazy<Command> = Lazy::new(Command::from_args);
 static DATABASE: Lazy<Db> = Lazy::new(|| sled::open(&db_path()).unwrap());
@@ -23,6 +26,124 @@ struct Command {
     pub index: Url,
     #[structopt(long, default_value = "5m", parse(try_from_str = humantime::parse_duration))]
     pub interval: Duration,
+    /// `/readyz` reports degraded once the last successful refresh is this many intervals old
+    #[structopt(long, default_value = "3")]
+    pub degraded_after: u32,
+    /// analysis results older than this are served while refreshed in background
+    #[structopt(long, default_value = "1m", parse(try_from_str = humantime::parse_duration))]
+    pub analysis_ttl: Duration,
+    /// analysis results older than this are not served even if they can't be refreshed
+    #[structopt(long, default_value = "1h", parse(try_from_str = humantime::parse_duration))]
+    pub analysis_max_stale: Duration,
+    /// analyses taking longer than this fail
+    #[structopt(long, default_value = "30s", parse(try_from_str = humantime::parse_duration))]
+    pub analysis_timeout: Duration,
+    /// maximum number of cached analysis results
+    #[structopt(long, default_value = "10240")]
+    pub analysis_capacity: usize,
+    /// maximum concurrent requests to each code hosting site
+    #[structopt(long, default_value = "16")]
+    pub fetch_concurrency: usize,
+    /// maximum bytes of upstream responses kept on disk
+    #[structopt(long, default_value = "268435456")]
+    pub http_cache_size: u64,
+    /// `max-age` of `Cache-Control` of badges and pages
+    #[structopt(long, default_value = "5m", parse(try_from_str = humantime::parse_duration))]
+    pub max_age: Duration,
+    /// dependencies without a release for this long are reported as stale
+    #[structopt(long, default_value = "2years", parse(try_from_str = humantime::parse_duration))]
+    pub stale_after: Duration,
+    /// git URL or local directory of the RustSec advisory database
+    #[structopt(
+        long,
+        default_value = "https://github.com/RustSec/advisory-db.git",
+        parse(from_str = parse_advisory_source)
+    )]
+    pub advisory_db: AdvisorySource,
+    /// branch of remote `--advisory-db` to follow
+    #[structopt(long, default_value = "master")]
+    pub advisory_branch: String,
+    /// extra directories of advisories in RustSec format as `name=path`, or `path` named after
+    /// the directory, could be given multiple times
+    #[structopt(
+        long = "local-advisories",
+        number_of_values = 1,
+        parse(from_str = parse_local_advisories)
+    )]
+    pub local_advisories: Vec<LocalAdvisories>,
+    /// log levels in `RUST_LOG` syntax, e.g. `info,deps_rs::fetch=trace`
+    #[structopt(long, env = "RUST_LOG", default_value = "info")]
+    pub log_level: LogFilter,
+    /// `text` or `json`
+    #[structopt(long, default_value = "text")]
+    pub log_format: LogFormat,
+    /// also write log into this file
+    #[structopt(long)]
+    pub log_file: Option<PathBuf>,
+    /// rotate log file once it grows beyond this many bytes
+    #[structopt(long, default_value = "10485760")]
+    pub log_file_size: u64,
+    /// number of rotated log files to keep
+    #[structopt(long, default_value = "5")]
+    pub log_file_keep: usize,
+    /// base URL this service is reachable at, used in badge snippets
+    #[structopt(long, default_value = "https://deps.rs")]
+    pub public_url: Url,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AdvisorySource {
+    /// git URL, cloned into cache directory and updated on every tick
+    Remote(String),
+    /// used as is, never fetched
+    Local(PathBuf),
+}
+
+fn parse_advisory_source(input: &str) -> AdvisorySource {
+    match Url::parse(input) {
+        Ok(url) if url.scheme() == "file" => match url.to_file_path() {
+            Ok(path) => AdvisorySource::Local(path),
+            Err(_) => AdvisorySource::Remote(url.to_string()),
+        },
+        Ok(url) => AdvisorySource::Remote(url.to_string()),
+        Err(_) if is_scp_like(input) => AdvisorySource::Remote(input.to_string()),
+        Err(_) => AdvisorySource::Local(PathBuf::from(input)),
+    }
+}
+
+/// Whether it's scp-like syntax of git, e.g. `git@github.com:RustSec/advisory-db.git`, which
+/// has a colon before any slash.
+fn is_scp_like(input: &str) -> bool {
+    match (input.find(':'), input.find('/')) {
+        (Some(0), _) => false,
+        (Some(colon), Some(slash)) => colon < slash,
+        (Some(_), None) => true,
+        (None, _) => false,
+    }
+}
+
+/// Directory of advisories, shown by name since its path is private to the server.
+#[derive(Debug, PartialEq)]
+pub struct LocalAdvisories {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+fn parse_local_advisories(input: &str) -> LocalAdvisories {
+    let mut parts = input.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(name), Some(path)) => LocalAdvisories {
+            name: name.to_string(),
+            path: PathBuf::from(path),
+        },
+        _ => {
+            let path = PathBuf::from(input);
+            let name = path
+                .file_name()
+                .map_or("local".to_string(), |x| x.to_string_lossy().into_owned());
+            LocalAdvisories { name, path }
+        }
+    }
 }
 
 pub fn init() -> AnyResult {
@@ -32,6 +153,26 @@ pub fn init() -> AnyResult {
     Ok(())
 }
 
+pub fn log_filter() -> &'static LogFilter {
+    &COMMAND.log_level
+}
+
+pub fn log_format() -> LogFormat {
+    COMMAND.log_format
+}
+
+pub fn log_file() -> Option<&'static PathBuf> {
+    COMMAND.log_file.as_ref()
+}
+
+pub fn log_file_size() -> u64 {
+    COMMAND.log_file_size
+}
+
+pub fn log_file_keep() -> usize {
+    COMMAND.log_file_keep
+}
+
 pub fn proxy() -> Option<&'static str> {
     COMMAND.proxy.as_ref().map(|x| x.as_str())
 }
@@ -44,14 +185,108 @@ pub fn tick_interval() -> Duration {
     COMMAND.interval
 }
 
+/// Age of last successful refresh beyond which the instance is degraded.
+pub fn degraded_after() -> Duration {
+    COMMAND.interval * COMMAND.degraded_after
+}
+
+pub fn analysis_ttl() -> Duration {
+    COMMAND.analysis_ttl
+}
+
+pub fn analysis_max_stale() -> Duration {
+    COMMAND.analysis_max_stale
+}
+
+pub fn analysis_timeout() -> Duration {
+    COMMAND.analysis_timeout
+}
+
+pub fn analysis_capacity() -> usize {
+    COMMAND.analysis_capacity
+}
+
+pub fn fetch_concurrency() -> usize {
+    COMMAND.fetch_concurrency
+}
+
+pub fn http_cache_size() -> u64 {
+    COMMAND.http_cache_size
+}
+
+pub fn max_age() -> Duration {
+    COMMAND.max_age
+}
+
+pub fn stale_after() -> Duration {
+    COMMAND.stale_after
+}
+
 pub fn cache_dir() -> PathBuf {
     COMMAND.cache.join("crates.io-index")
 }
 
+pub fn advisory_db() -> &'static AdvisorySource {
+    &COMMAND.advisory_db
+}
+
+pub fn advisory_branch() -> &'static str {
+    &COMMAND.advisory_branch
+}
+
+pub fn local_advisories() -> &'static [LocalAdvisories] {
+    &COMMAND.local_advisories
+}
+
+pub fn advisory_dir() -> PathBuf {
+    COMMAND.cache.join("advisory-db")
+}
+
 pub fn index_url() -> &'static str {
     COMMAND.index.as_str()
 }
 
+/// Public base URL without trailing slash.
+pub fn public_url() -> &'static str {
+    COMMAND.public_url.as_str().trim_end_matches('/')
+}
+
 pub fn database() -> &'static Db {
     &DATABASE
 }
+
+#[test]
+fn test_parse_advisory_source() {
+    let remote = |x: &str| AdvisorySource::Remote(x.to_string());
+    assert_eq!(
+        parse_advisory_source("https://github.com/RustSec/advisory-db.git"),
+        remote("https://github.com/RustSec/advisory-db.git")
+    );
+    assert_eq!(
+        parse_advisory_source("git@github.com:RustSec/advisory-db.git"),
+        remote("git@github.com:RustSec/advisory-db.git")
+    );
+    assert_eq!(
+        parse_advisory_source("./advisory-db"),
+        AdvisorySource::Local(PathBuf::from("./advisory-db"))
+    );
+    assert_eq!(
+        parse_advisory_source("file:///srv/advisory-db"),
+        AdvisorySource::Local(PathBuf::from("/srv/advisory-db"))
+    );
+}
+
+#[test]
+fn test_parse_local_advisories() {
+    assert_eq!(
+        parse_local_advisories("internal=/srv/advisories"),
+        LocalAdvisories {
+            name: "internal".to_string(),
+            path: PathBuf::from("/srv/advisories"),
+        }
+    );
+    assert_eq!(
+        parse_local_advisories("/srv/advisories/").name,
+        "advisories"
+    );
+}
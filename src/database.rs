@@ -1,16 +1,50 @@
-use std::{collections::HashSet, sync::RwLock};
-
-use git2::{FetchOptions, FetchPrune, ObjectType, Oid, ProxyOptions, Repository, TreeWalkMode};
+use std::{
+    collections::HashSet,
+    convert::TryInto,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        RwLock,
+    },
+};
+
+use chrono::{DateTime, TimeZone, Utc};
+use git2::{
+    FetchOptions, FetchPrune, ObjectType, Oid, ProxyOptions, Repository, ResetType, Sort,
+    TreeWalkMode,
+};
 use once_cell::sync::Lazy;
-use rustsec::{Collection, Database};
+use rustsec::{database::Query, Advisory, Collection, Database};
 use semver::{Version, VersionReq};
 use sled::Tree;
 
-use crate::utils::AnyResult;
+use crate::{command::AdvisorySource, utils::AnyResult};
 
 static INDEX_DB: Lazy<Tree> = Lazy::new(|| crate::command::database().open_tree("index").unwrap());
 static EXTRA_DB: Lazy<Tree> = Lazy::new(|| crate::command::database().open_tree("extra").unwrap());
-static AUDIT_DB: Lazy<RwLock<Database>> = Lazy::new(|| RwLock::new(Database::fetch().unwrap()));
+/// `{name}@{version}` -> commit time of the first index commit seeing it, big endian seconds
+static RELEASE_DB: Lazy<Tree> =
+    Lazy::new(|| crate::command::database().open_tree("release").unwrap());
+static AUDIT_DB: Lazy<RwLock<Option<Database>>> = Lazy::new(|| RwLock::new(None));
+static AUDIT_STATE: Lazy<RwLock<RefreshState>> = Lazy::new(Default::default);
+static TICK_STATE: Lazy<RwLock<RefreshState>> = Lazy::new(Default::default);
+/// `LAST_LOADED_TREE_ID` kept in memory, since it's read for every response
+static TREE_ID: Lazy<RwLock<Option<Oid>>> = Lazy::new(|| {
+    let tree_id = EXTRA_DB
+        .get(LAST_LOADED_TREE_ID)
+        .ok()
+        .flatten()
+        .and_then(|x| Oid::from_bytes(&x).ok());
+    RwLock::new(tree_id)
+});
+/// number of crates in `INDEX_DB`, kept since `Tree::len` scans the whole tree
+static CRATE_COUNT: AtomicUsize = AtomicUsize::new(0);
+static LOCAL_ADVISORIES: Lazy<RwLock<Vec<LocalAdvisory>>> = Lazy::new(Default::default);
+
+const RUSTSEC_SOURCE: &str = "RustSec";
+const LAST_LOADED_TREE_ID: &str = "last_loaded_tree_id";
+/// set once release times are recorded from all history of the index
+const RELEASE_TIMES_BACKFILLED: &str = "release_times_backfilled";
 
 pub fn init() -> AnyResult {
     fn tick() -> AnyResult {
@@ -22,20 +56,55 @@ pub fn init() -> AnyResult {
         let begin = std::time::Instant::now();
         fresh_index_db()?;
         debug!("fresh index used {:?}", begin.elapsed());
+        CRATE_COUNT.store(INDEX_DB.len(), Ordering::Relaxed);
+        crate::metrics::observe_index_refresh(begin.elapsed());
+
+        let begin = std::time::Instant::now();
+        if fresh_audit_db() {
+            debug!("fresh audit database used: {:?}", begin.elapsed());
+        }
 
         let begin = std::time::Instant::now();
-        let new_db = rustsec::Database::fetch()?;
-        *AUDIT_DB.write().unwrap() = new_db;
-        debug!("fresh audit database used: {:?}", begin.elapsed());
+        fresh_local_advisories();
+        debug!("fresh local advisories used: {:?}", begin.elapsed());
 
         Ok(())
     }
 
-    debug!("fetching audit database");
-    // TODO: fetching audit database via proxy
-    Lazy::force(&AUDIT_DB);
+    fn tick_and_record() -> AnyResult {
+        let result = tick();
+        let mut state = TICK_STATE.write().unwrap();
+        match &result {
+            Ok(()) => {
+                state.last_success = Some(Utc::now());
+                state.last_error = None;
+            }
+            Err(error) => {
+                state.last_failure = Some(Utc::now());
+                state.last_error = Some(error.to_string());
+            }
+        }
+        result
+    }
+
     debug!("creating crate database");
-    tick()?;
+    Lazy::force(&TREE_ID);
+    CRATE_COUNT.store(INDEX_DB.len(), Ordering::Relaxed);
+    // keep serving what's on disk from the last run, e.g. when network is down at startup
+    if let Err(error) = tick_and_record() {
+        error!(
+            "failed to create crate database, using the one on disk: {}",
+            error
+        );
+        fresh_local_advisories();
+    }
+    if AUDIT_DB.read().unwrap().is_none() {
+        warn!("falling back to audit database on disk");
+        match load_audit_db() {
+            Ok(db) => *AUDIT_DB.write().unwrap() = Some(db),
+            Err(error) => error!("failed to load audit database from disk: {:?}", error),
+        }
+    }
 
     std::thread::spawn(|| loop {
         let sleep_duration = crate::command::tick_interval();
@@ -45,7 +114,7 @@ pub fn init() -> AnyResult {
         );
         std::thread::sleep(sleep_duration);
 
-        if let Err(error) = tick() {
+        if let Err(error) = tick_and_record() {
             error!("failed to fresh version db: {}", error);
         }
     });
@@ -53,6 +122,186 @@ pub fn init() -> AnyResult {
     Ok(())
 }
 
+/// Result of the latest attempts to refresh a data source.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct RefreshState {
+    pub last_success: Option<DateTime<Utc>>,
+    pub last_failure: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+}
+
+pub fn audit_state() -> RefreshState {
+    AUDIT_STATE.read().unwrap().clone()
+}
+
+/// State of the periodic refresh of index and advisories as a whole.
+pub fn tick_state() -> RefreshState {
+    TICK_STATE.read().unwrap().clone()
+}
+
+/// Number of crates in the loaded index, including one loaded by a previous run.
+pub fn crate_count() -> usize {
+    CRATE_COUNT.load(Ordering::Relaxed)
+}
+
+/// Number of advisories from all sources, `None` before audit database is loaded.
+pub fn advisory_count() -> Option<usize> {
+    let audit_db = AUDIT_DB.read().unwrap();
+    let count = audit_db.as_ref()?.iter().count();
+    Some(count + LOCAL_ADVISORIES.read().unwrap().len())
+}
+
+/// Fetch and reload audit database, keeps the loaded one if anything goes wrong.
+fn fresh_audit_db() -> bool {
+    let result = fetch_audit_db().and_then(|()| load_audit_db());
+    let mut state = AUDIT_STATE.write().unwrap();
+    match result {
+        Ok(db) => {
+            *AUDIT_DB.write().unwrap() = Some(db);
+            state.last_success = Some(Utc::now());
+            state.last_error = None;
+            true
+        }
+        Err(error) => {
+            error!("failed to fresh audit database: {:?}", error);
+            state.last_failure = Some(Utc::now());
+            state.last_error = Some(error.to_string());
+            false
+        }
+    }
+}
+
+fn fetch_audit_db() -> AnyResult {
+    let url = match crate::command::advisory_db() {
+        AdvisorySource::Local(_) => return Ok(()),
+        AdvisorySource::Remote(url) => url.as_str(),
+    };
+
+    let advisory_dir = crate::command::advisory_dir();
+    if !advisory_dir.exists() {
+        std::fs::create_dir_all(&advisory_dir)?;
+        debug!("created advisory directory {}", advisory_dir.display());
+    }
+
+    let repo = Repository::init(&advisory_dir)?;
+    let branch = crate::command::advisory_branch();
+    fetch_upstream(&repo, url, branch)?;
+
+    let commit = repo
+        .find_reference(&format!("refs/remotes/upstream/{}", branch))?
+        .peel_to_commit()?;
+    repo.reset(commit.as_object(), ResetType::Hard, None)?;
+
+    Ok(())
+}
+
+fn load_audit_db() -> AnyResult<Database> {
+    let path = match crate::command::advisory_db() {
+        AdvisorySource::Local(path) => path.clone(),
+        AdvisorySource::Remote(_) => crate::command::advisory_dir(),
+    };
+    load_advisory_repo(&path)
+}
+
+fn load_advisory_repo(path: &Path) -> AnyResult<Database> {
+    let repo = rustsec::Repository::open(path)?;
+    Ok(Database::load(&repo)?)
+}
+
+struct LocalAdvisory {
+    source: String,
+    advisory: Advisory,
+}
+
+/// Re-read every local advisory directory, a broken file is skipped instead of dropping the others.
+fn fresh_local_advisories() {
+    let mut result = vec![];
+    for dir in crate::command::local_advisories() {
+        let mut files = vec![];
+        if let Err(error) = collect_advisory_files(&dir.path, &mut files) {
+            error!(
+                "failed to list advisories in {}: {:?}",
+                dir.path.display(),
+                error
+            );
+            continue;
+        }
+
+        for file in files {
+            match load_advisory_file(&file) {
+                Ok(advisory) => result.push(LocalAdvisory {
+                    source: dir.name.clone(),
+                    advisory,
+                }),
+                Err(error) => error!("failed to load advisory {}: {:?}", file.display(), error),
+            }
+        }
+    }
+
+    debug!("loaded {} local advisories", result.len());
+    *LOCAL_ADVISORIES.write().unwrap() = result;
+}
+
+fn collect_advisory_files(dir: &Path, output: &mut Vec<PathBuf>) -> AnyResult {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_advisory_files(&path, output)?;
+            continue;
+        }
+        if path.file_name().and_then(|x| x.to_str()) == Some("README.md") {
+            continue;
+        }
+        match path.extension().and_then(|x| x.to_str()) {
+            Some("toml") | Some("md") => output.push(path),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Load advisory in either legacy TOML format, or Markdown format with a leading TOML block.
+fn load_advisory_file(path: &Path) -> AnyResult<Advisory> {
+    let content = std::fs::read_to_string(path)?;
+    if path.extension().and_then(|x| x.to_str()) == Some("toml") {
+        return Ok(content.parse()?);
+    }
+
+    let content = content
+        .trim_start()
+        .strip_prefix("```toml")
+        .ok_or_else(|| anyhow!("missing leading TOML block"))?;
+    let end = content
+        .find("\n```")
+        .ok_or_else(|| anyhow!("unterminated TOML block"))?;
+    let mut front: toml::Value = toml::from_str(&content[..end])?;
+    let markdown = content[end + 4..].trim();
+
+    let (title, description) = match markdown.strip_prefix("# ") {
+        Some(x) => {
+            let mut parts = x.splitn(2, '\n');
+            let title = parts.next().unwrap_or_default().trim();
+            let description = parts.next().unwrap_or_default().trim();
+            (title.to_string(), description.to_string())
+        }
+        None => bail!("missing title"),
+    };
+
+    let metadata = front
+        .get_mut("advisory")
+        .and_then(|x| x.as_table_mut())
+        .ok_or_else(|| anyhow!("missing [advisory] section"))?;
+    metadata.entry("title").or_insert(title.into());
+    metadata.entry("description").or_insert(description.into());
+
+    Ok(toml::to_string(&front)?.parse()?)
+}
+
+/// Tree id of the crates index currently loaded.
+pub fn index_tree_id() -> Option<Oid> {
+    *TREE_ID.read().unwrap()
+}
+
 pub fn get_crate_metas(crate_name: &str) -> AnyResult<Option<Vec<CrateMeta>>> {
     let key = crate_name.as_bytes();
     let content = match INDEX_DB.get(key)? {
@@ -65,14 +314,66 @@ pub fn get_crate_metas(crate_name: &str) -> AnyResult<Option<Vec<CrateMeta>>> {
     Ok(Some(result))
 }
 
-pub fn is_insecure(name: &str, version: Version) -> AnyResult<bool> {
+/// When given version first appeared in the index, at latest, `None` until history of the index is
+/// backfilled after the first load.
+pub fn get_release_time(name: &str, version: &Version) -> AnyResult<Option<DateTime<Utc>>> {
+    let content = match RELEASE_DB.get(release_key(name, version))? {
+        None => return Ok(None),
+        Some(x) => x,
+    };
+    let seconds = i64::from_be_bytes(content.as_ref().try_into()?);
+    Ok(Some(Utc.timestamp(seconds, 0)))
+}
+
+fn release_key(name: &str, version: &Version) -> String {
+    format!("{}@{}", name, version)
+}
+
+/// Advisories affecting given crate version, from RustSec and every local advisory directory.
+pub fn find_advisories(name: &str, version: Version) -> AnyResult<Vec<AdvisoryRecord>> {
     let package: rustsec::package::Name = name.parse()?;
-    let query = rustsec::database::Query::new()
+    let query = Query::new().package_version(package.clone(), version.clone());
+    let crates_query = Query::new()
         .collection(Collection::Crates)
         .package_version(package, version);
 
-    let safe = AUDIT_DB.read().unwrap().query(&query).is_empty();
-    Ok(!safe)
+    let mut result: Vec<AdvisoryRecord> = match &*AUDIT_DB.read().unwrap() {
+        Some(db) => db
+            .query(&crates_query)
+            .into_iter()
+            .map(|x| AdvisoryRecord::new(x, RUSTSEC_SOURCE))
+            .collect(),
+        None => bail!("audit database not loaded"),
+    };
+
+    result.extend(
+        LOCAL_ADVISORIES
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|x| query.matches(&x.advisory))
+            .map(|x| AdvisoryRecord::new(&x.advisory, &x.source)),
+    );
+
+    Ok(result)
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Eq, PartialEq)]
+pub struct AdvisoryRecord {
+    pub id: String,
+    pub title: String,
+    /// where this advisory comes from, "RustSec" or name of local advisory directory
+    pub source: String,
+}
+
+impl AdvisoryRecord {
+    fn new(advisory: &Advisory, source: &str) -> Self {
+        Self {
+            id: advisory.metadata.id.to_string(),
+            title: advisory.metadata.title.clone(),
+            source: source.to_string(),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -104,6 +405,16 @@ pub enum DependencyKind {
     Dev,
 }
 
+impl DependencyKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            DependencyKind::Normal => "normal",
+            DependencyKind::Build => "build",
+            DependencyKind::Dev => "dev",
+        }
+    }
+}
+
 struct CrateDB {
     index: Tree,
     extra: Tree,
@@ -117,11 +428,16 @@ fn fetch_index() -> AnyResult {
     }
 
     let repo = Repository::init_bare(&index_dir)?;
+    fetch_upstream(&repo, crate::command::index_url(), "master")
+}
+
+/// Fetch `branch` of given URL into `refs/remotes/upstream/{branch}`, via proxy if configured.
+fn fetch_upstream(repo: &Repository, url: &str, branch: &str) -> AnyResult {
     if repo.find_remote("upstream").is_err() {
-        repo.remote("upstream", crate::command::index_url())?;
-        debug!("created remote: {}", crate::command::index_url());
+        repo.remote("upstream", url)?;
+        debug!("created remote: {}", url);
     }
-    repo.remote_set_url("upstream", crate::command::index_url())?;
+    repo.remote_set_url("upstream", url)?;
 
     let mut proxy_option = ProxyOptions::new();
     if let Some(proxy_url) = &crate::command::proxy() {
@@ -134,11 +450,9 @@ fn fetch_index() -> AnyResult {
     fetch_option.prune(FetchPrune::On);
     fetch_option.proxy_options(proxy_option);
 
-    repo.find_remote("upstream")?.fetch(
-        &["+refs/heads/master:refs/remotes/upstream/master"],
-        Some(&mut fetch_option),
-        None,
-    )?;
+    let refspec = format!("+refs/heads/{0}:refs/remotes/upstream/{0}", branch);
+    repo.find_remote("upstream")?
+        .fetch(&[refspec.as_str()], Some(&mut fetch_option), None)?;
 
     Ok(())
 }
@@ -155,13 +469,14 @@ fn fresh_index_db() -> AnyResult {
         Ok(())
     }
 
-    const LAST_LOADED_TREE_ID: &str = "last_loaded_tree_id";
+    const LAST_LOADED_COMMIT_ID: &str = "last_loaded_commit_id";
 
     let index_dir = crate::command::cache_dir();
     let repo = Repository::open_bare(&index_dir)?;
-    let new_tree = repo
+    let new_commit = repo
         .find_reference("refs/remotes/upstream/master")?
-        .peel_to_tree()?;
+        .peel_to_commit()?;
+    let new_tree = new_commit.tree()?;
 
     let old_tree_id = EXTRA_DB
         .get(LAST_LOADED_TREE_ID)?
@@ -171,6 +486,20 @@ fn fresh_index_db() -> AnyResult {
         return Ok(());
     }
 
+    let old_commit_id = EXTRA_DB
+        .get(LAST_LOADED_COMMIT_ID)?
+        .map(|x| Oid::from_bytes(&*x).expect("broken 'last_loaded_commit_id'"));
+    if let Some(old_commit_id) = old_commit_id {
+        let begin = std::time::Instant::now();
+        if let Err(e) = record_release_times(&repo, Some(old_commit_id), new_commit.id()) {
+            error!("failed to record release times: {:?}", e);
+        }
+        debug!("record release times used {:?}", begin.elapsed());
+    }
+    if EXTRA_DB.get(RELEASE_TIMES_BACKFILLED)?.is_none() {
+        backfill_release_times(new_commit.id());
+    }
+
     let mut old_ids = HashSet::new();
     if let Some(old_tree_id) = old_tree_id {
         repo.find_tree(old_tree_id)?
@@ -202,12 +531,7 @@ fn fresh_index_db() -> AnyResult {
             }
         };
 
-        let content = blob
-            .content()
-            .split(|x| *x == b'\n')
-            .map(|line| serde_json::from_slice::<CrateMeta>(line))
-            .filter_map(|x| x.ok())
-            .collect::<Vec<_>>();
+        let content = parse_crate_metas(blob.content());
         if content.is_empty() {
             error!("no valid crate meta found in {}/{:?}", pwd, entry.name());
             return 0;
@@ -221,6 +545,111 @@ fn fresh_index_db() -> AnyResult {
     })?;
 
     EXTRA_DB.insert(LAST_LOADED_TREE_ID, new_tree.id().as_bytes())?;
+    *TREE_ID.write().unwrap() = Some(new_tree.id());
+    EXTRA_DB.insert(LAST_LOADED_COMMIT_ID, new_commit.id().as_bytes())?;
 
     Ok(())
 }
+
+fn parse_crate_metas(content: &[u8]) -> Vec<CrateMeta> {
+    content
+        .split(|x| *x == b'\n')
+        .map(|line| serde_json::from_slice::<CrateMeta>(line))
+        .filter_map(|x| x.ok())
+        .collect()
+}
+
+/// Record release times from all history of the index in background, since it reads every
+/// version of every crate and would hold the first load for long.
+fn backfill_release_times(commit_id: Oid) {
+    fn backfill(commit_id: Oid) -> AnyResult {
+        let repo = Repository::open_bare(crate::command::cache_dir())?;
+        record_release_times(&repo, None, commit_id)?;
+        EXTRA_DB.insert(RELEASE_TIMES_BACKFILLED, &[1u8][..])?;
+        Ok(())
+    }
+
+    static RUNNING: AtomicBool = AtomicBool::new(false);
+    if RUNNING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    std::thread::spawn(move || {
+        let begin = std::time::Instant::now();
+        match backfill(commit_id) {
+            Ok(()) => debug!("backfill release times used {:?}", begin.elapsed()),
+            Err(e) => error!("failed to backfill release times: {:?}", e),
+        }
+        RUNNING.store(false, Ordering::SeqCst);
+    });
+}
+
+/// Walk index commits since `old_commit_id`, or all of them if `None`, every version added by a
+/// commit is released at its time. The root commit of a squashed index adds every version released
+/// before it, so its time is only an upper bound of theirs.
+fn record_release_times(
+    repo: &Repository,
+    old_commit_id: Option<Oid>,
+    new_commit_id: Oid,
+) -> AnyResult {
+    let read_metas = |id: Oid| -> Vec<CrateMeta> {
+        if id.is_zero() {
+            return vec![];
+        }
+        match repo.find_blob(id) {
+            Ok(blob) => parse_crate_metas(blob.content()),
+            Err(e) => {
+                error!("failed to find blob: {}, id: {}", e, id);
+                vec![]
+            }
+        }
+    };
+
+    let mut walk = repo.revwalk()?;
+    walk.push(new_commit_id)?;
+    if let Some(old_commit_id) = old_commit_id {
+        walk.hide(old_commit_id)?;
+    }
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE);
+
+    for id in walk {
+        let commit = repo.find_commit(id?)?;
+        let parent_tree = match commit.parents().next() {
+            None => None,
+            Some(x) => Some(x.tree()?),
+        };
+        let seconds = commit.time().seconds();
+        let diff = repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&commit.tree()?), None)?;
+
+        for delta in diff.deltas() {
+            let old_versions = read_metas(delta.old_file().id())
+                .into_iter()
+                .map(|x| x.vers)
+                .collect::<HashSet<_>>();
+            for meta in read_metas(delta.new_file().id()) {
+                if old_versions.contains(&meta.vers) {
+                    continue;
+                }
+                // the earliest commit wins, backfill and refresh could run at the same time
+                let key = release_key(&meta.name, &meta.vers);
+                let _ = RELEASE_DB.compare_and_swap(
+                    key,
+                    None as Option<&[u8]>,
+                    Some(&seconds.to_be_bytes()[..]),
+                )?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_load_advisory_file() {
+    let advisory = load_advisory_file(Path::new("fixture/advisory.md")).unwrap();
+    assert_eq!(advisory.metadata.id.to_string(), "RUSTSEC-2020-0001");
+    assert_eq!(advisory.metadata.title, "Foo is broken");
+    assert_eq!(advisory.metadata.description, "Details here.");
+
+    assert!(load_advisory_file(Path::new("fixture/manifest.toml")).is_err());
+}
@@ -1,64 +1,265 @@
-use std::sync::Mutex;
+use std::{
+    convert::TryInto,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
 
-use lru::LruCache;
+use chrono::Utc;
 use once_cell::sync::Lazy;
-use sled::{IVec, Tree};
+use reqwest::header::{HeaderMap, CACHE_CONTROL, ETAG, LAST_MODIFIED};
+use sled::Tree;
 
 use crate::utils::AnyResult;
 
-/// URL -> ETAG
-static URL_TO_ETAG: Lazy<Mutex<LruCache<String, String>>> =
-    Lazy::new(|| Mutex::new(LruCache::unbounded()));
+/// URL -> `CacheEntry`
+static CACHE_DB: Lazy<Tree> =
+    Lazy::new(|| crate::command::database().open_tree("http-cache").unwrap());
 
-/// ETAG -> HTTP Response Body
-static CACHE_DATA_DB: Lazy<Tree> = Lazy::new(|| {
-    let result = crate::command::database()
-        .open_tree("http-cache-data")
-        .unwrap();
-    result.clear().expect("failed to clean http cache on disk"); // clean previous cache
-    result
+/// URL -> `stored_at` and size of its `CACHE_DB` value, scanned on eviction instead of bodies
+static META_DB: Lazy<Tree> = Lazy::new(|| {
+    crate::command::database()
+        .open_tree("http-cache-meta")
+        .unwrap()
+});
+
+/// total size of values in `CACHE_DB`
+static CACHE_BYTES: Lazy<AtomicU64> = Lazy::new(|| {
+    let bytes = META_DB
+        .iter()
+        .filter_map(|x| x.ok())
+        .map(|(_, value)| decode_meta(&value).1)
+        .sum();
+    AtomicU64::new(bytes)
 });
 
-const CACHE_LIMIT: usize = 10240;
+/// freshness of a missing file when upstream doesn't tell, in seconds
+const NOT_FOUND_MAX_AGE: i64 = 300;
+
+/// set while an eviction is scheduled or running
+static EVICTING: AtomicBool = AtomicBool::new(false);
+
+/// A response body along with what's needed to tell whether and how to revalidate it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// seconds since epoch, validated or stored
+    pub stored_at: i64,
+    /// from `max-age` of `Cache-Control`, revalidated on every use if absent
+    pub max_age: Option<i64>,
+    pub body: Vec<u8>,
+    /// upstream responded 404, so optional files missing from most repos aren't asked every time
+    pub not_found: bool,
+}
+
+impl CacheEntry {
+    /// `None` if response shouldn't or couldn't be cached.
+    pub fn new(headers: &HeaderMap, body: Vec<u8>) -> Option<Self> {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|x| x.to_str().ok())
+                .map(String::from)
+        };
+        let mut entry = CacheEntry {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+            stored_at: 0,
+            max_age: None,
+            body,
+            not_found: false,
+        };
+        if !entry.revalidated(headers) {
+            return None;
+        }
+        if entry.etag.is_none() && entry.last_modified.is_none() && entry.max_age.is_none() {
+            return None;
+        }
+        Some(entry)
+    }
+
+    /// Remember a missing file, `None` if response shouldn't be cached.
+    pub fn not_found(headers: &HeaderMap) -> Option<Self> {
+        let mut entry = CacheEntry {
+            etag: None,
+            last_modified: None,
+            stored_at: 0,
+            max_age: None,
+            body: vec![],
+            not_found: true,
+        };
+        if !entry.revalidated(headers) {
+            return None;
+        }
+        entry.max_age = entry.max_age.or(Some(NOT_FOUND_MAX_AGE));
+        Some(entry)
+    }
+
+    /// Update freshness from headers of a new response, `false` if it must not be stored.
+    pub fn revalidated(&mut self, headers: &HeaderMap) -> bool {
+        let cache_control = headers
+            .get_all(CACHE_CONTROL)
+            .iter()
+            .filter_map(|x| x.to_str().ok())
+            .flat_map(|x| x.split(','))
+            .map(|x| x.trim().to_ascii_lowercase())
+            .collect::<Vec<_>>();
+        if cache_control.iter().any(|x| x == "no-store") {
+            return false;
+        }
+        // a 304 without `Cache-Control` keeps the stored freshness
+        if !cache_control.is_empty() {
+            self.max_age = if cache_control.iter().any(|x| x == "no-cache") {
+                None
+            } else {
+                cache_control
+                    .iter()
+                    .filter_map(|x| x.strip_prefix("max-age="))
+                    .find_map(|x| x.parse().ok())
+            };
+        }
+        self.stored_at = Utc::now().timestamp();
+        true
+    }
+
+    /// Whether it could be used without asking upstream.
+    pub fn is_fresh(&self) -> bool {
+        match self.max_age {
+            None => false,
+            Some(max_age) => Utc::now().timestamp() < self.stored_at + max_age,
+        }
+    }
+}
 
 pub fn init() -> AnyResult {
-    Lazy::force(&CACHE_DATA_DB);
-    Lazy::force(&URL_TO_ETAG);
+    // bodies used to be keyed by ETag in this tree
+    crate::command::database().drop_tree("http-cache-data")?;
+    // entries written before sizes were tracked separately can't be evicted
+    if META_DB.is_empty() {
+        CACHE_DB.clear()?;
+    }
+    Lazy::force(&CACHE_BYTES);
+    crate::metrics::set_http_cache_bytes(CACHE_BYTES.load(Ordering::Relaxed));
     Ok(())
 }
 
-#[allow(clippy::ptr_arg)]
-pub fn get(url: &String) -> AnyResult<Option<(String, IVec)>> {
-    let mut cache = URL_TO_ETAG.lock().unwrap();
-
-    let etag: &String = match cache.get(url) {
+pub fn get(url: &str) -> AnyResult<Option<CacheEntry>> {
+    let value = match CACHE_DB.get(url)? {
         Some(x) => x,
         None => return Ok(None),
     };
+    match bincode::deserialize(&value) {
+        Ok(x) => Ok(Some(x)),
+        Err(e) => {
+            warn!("broken http cache of {}: {}", url, e);
+            Ok(None)
+        }
+    }
+}
 
-    let data: IVec = match CACHE_DATA_DB.get(etag.as_bytes())? {
-        Some(x) => x,
-        None => return Ok(None),
-    };
+pub fn set(url: &str, entry: &CacheEntry) -> AnyResult {
+    let value = bincode::serialize(entry)?;
+    let new_len = value.len() as u64;
+    let old_len = CACHE_DB.insert(url, value)?.map_or(0, |x| x.len() as u64);
+    META_DB.insert(url, &encode_meta(entry.stored_at, new_len)[..])?;
 
-    Ok(Some((etag.clone(), data)))
+    let bytes = update_bytes(new_len, old_len);
+    if bytes > crate::command::http_cache_size() && !EVICTING.swap(true, Ordering::SeqCst) {
+        actix_rt::spawn(async {
+            match crate::analyze::blocking(evict).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => error!("failed to evict http cache: {:?}", e),
+                Err(e) => error!("failed to evict http cache: {}", e),
+            }
+            EVICTING.store(false, Ordering::SeqCst);
+        });
+    }
+    crate::metrics::set_http_cache_bytes(bytes);
+
+    Ok(())
 }
 
-pub fn set(url: String, data: IVec, etag: String) -> AnyResult {
-    CACHE_DATA_DB.insert(etag.as_bytes(), data)?;
+/// Account for `added` and `removed` bytes at once, so concurrent writers and eviction never see
+/// it underflow.
+fn update_bytes(added: u64, removed: u64) -> u64 {
+    let update = |x: u64| x.saturating_add(added).saturating_sub(removed);
+    let old = CACHE_BYTES
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |x| Some(update(x)))
+        .unwrap();
+    update(old)
+}
 
-    let mut cache = URL_TO_ETAG.lock().unwrap();
+fn encode_meta(stored_at: i64, len: u64) -> [u8; 16] {
+    let mut meta = [0; 16];
+    meta[..8].copy_from_slice(&stored_at.to_be_bytes());
+    meta[8..].copy_from_slice(&len.to_be_bytes());
+    meta
+}
 
-    cache.put(url, etag);
+/// `stored_at` and size, the oldest possible if broken
+fn decode_meta(meta: &[u8]) -> (i64, u64) {
+    match (meta.get(..8), meta.get(8..16)) {
+        (Some(stored_at), Some(len)) => (
+            i64::from_be_bytes(stored_at.try_into().unwrap()),
+            u64::from_be_bytes(len.try_into().unwrap()),
+        ),
+        _ => (i64::min_value(), 0),
+    }
+}
 
-    if cache.len() > CACHE_LIMIT {
-        let (_, etag) = cache.pop_lru().unwrap();
-        if let Err(e) = CACHE_DATA_DB.remove(etag.as_bytes()) {
-            error!("failed to remove cache item from disk: {:?}", e);
-        }
+/// Drop the least recently validated entries till only 90% of size limit is used, on the
+/// blocking pool since it scans every entry.
+fn evict() -> AnyResult {
+    let mut entries = vec![];
+    for item in META_DB.iter() {
+        let (key, value) = item?;
+        let (stored_at, len) = decode_meta(&value);
+        entries.push((stored_at, key, len));
     }
+    entries.sort_by_key(|(stored_at, _, _)| *stored_at);
 
-    drop(cache);
+    let target = crate::command::http_cache_size() * 9 / 10;
+    let mut bytes: u64 = entries.iter().map(|(_, _, len)| len).sum();
+    let mut evicted = 0;
+    for (_, key, len) in entries {
+        if bytes <= target {
+            break;
+        }
+        // the entry may have been replaced since scanned, take the size actually removed
+        let removed = CACHE_DB.remove(&key)?.map_or(0, |x| x.len() as u64);
+        META_DB.remove(&key)?;
+        bytes = bytes.saturating_sub(len);
+        crate::metrics::set_http_cache_bytes(update_bytes(0, removed));
+        evicted += 1;
+    }
 
+    crate::metrics::observe_http_cache_evictions(evicted);
+    debug!("evicted {} http cache entries", evicted);
     Ok(())
 }
+
+#[test]
+fn test_cache_entry_freshness() {
+    use reqwest::header::HeaderValue;
+
+    let mut headers = HeaderMap::new();
+    headers.insert(ETAG, HeaderValue::from_static("\"abc\""));
+    headers.insert(
+        CACHE_CONTROL,
+        HeaderValue::from_static("public, max-age=300"),
+    );
+    let mut entry = CacheEntry::new(&headers, vec![]).unwrap();
+    assert_eq!(entry.max_age, Some(300));
+    assert!(entry.is_fresh());
+    assert!(entry.revalidated(&HeaderMap::new()));
+    assert_eq!(entry.max_age, Some(300));
+
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    let entry = CacheEntry::new(&headers, vec![]).unwrap();
+    assert!(!entry.is_fresh());
+
+    let entry = CacheEntry::not_found(&HeaderMap::new()).unwrap();
+    assert!(entry.not_found && entry.is_fresh());
+
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    assert!(CacheEntry::new(&headers, vec![]).is_none());
+}
@@ -4,6 +4,7 @@ use bytes::Buf;
 use once_cell::sync::Lazy;
 use reqwest::{Client, Proxy, Response};
 use sled::IVec;
+use tokio::sync::Semaphore;
 
 use crate::{
     model::{RepoIdentity, Site},
@@ -12,6 +13,18 @@ use crate::{
 
 mod cache;
 
+use self::cache::CacheEntry;
+
+/// Limits concurrent requests to each site, indexed by `Site as usize`.
+static HOST_LIMITS: Lazy<[Semaphore; 3]> = Lazy::new(|| {
+    let limit = crate::command::fetch_concurrency();
+    [
+        Semaphore::new(limit),
+        Semaphore::new(limit),
+        Semaphore::new(limit),
+    ]
+});
+
 static GLOBAL_CLIENT: Lazy<Client> = Lazy::new(|| match init_client() {
     Ok(x) => x,
     Err(e) => {
@@ -36,55 +49,141 @@ fn init_client() -> AnyResult<Client> {
     Ok(builder.build()?)
 }
 
+/// Same as `fetch`, but a missing file is not an error.
+pub async fn fetch_optional(ident: &RepoIdentity, rel_path: &Path) -> AnyResult<Option<IVec>> {
+    match fetch(ident, rel_path).await {
+        Ok(x) => Ok(Some(x)),
+        Err(e) if is_not_found(&e) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Upstream responded 404, or did so recently.
+#[derive(Debug)]
+pub struct NotFound(String);
+
+impl std::fmt::Display for NotFound {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "not found: {}", self.0)
+    }
+}
+
+impl std::error::Error for NotFound {}
+
+/// Whether given error of `fetch` means the file doesn't exist upstream.
+pub fn is_not_found(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<NotFound>().is_some()
+}
+
 pub async fn fetch(ident: &RepoIdentity, rel_path: &Path) -> AnyResult<IVec> {
+    let git_ref = ident.git_ref.as_deref().unwrap_or("HEAD");
+    let rel_path = match &ident.path {
+        None => rel_path.to_path_buf(),
+        Some(base) => Path::new(base).join(rel_path),
+    };
     let url = match ident.site {
         Site::GitHub => format!(
-            "https://raw.githubusercontent.com/{owner}/{repo}/HEAD/{rel_path}",
+            "https://raw.githubusercontent.com/{owner}/{repo}/{git_ref}/{rel_path}",
             owner = ident.owner,
             repo = ident.repo,
+            git_ref = git_ref,
             rel_path = rel_path.display()
         ),
         Site::GitLab => format!(
-            "https://gitlab.com/{owner}/{repo}/raw/HEAD/{rel_path}",
+            "https://gitlab.com/{owner}/{repo}/raw/{git_ref}/{rel_path}",
             owner = ident.owner,
             repo = ident.repo,
+            git_ref = git_ref,
             rel_path = rel_path.display()
         ),
         Site::BitBucket => format!(
-            "https://bitbucket.org/{owner}/{repo}/raw/HEAD/{rel_path}",
+            "https://bitbucket.org/{owner}/{repo}/raw/{git_ref}/{rel_path}",
             owner = ident.owner,
             repo = ident.repo,
+            git_ref = git_ref,
             rel_path = rel_path.display()
         ),
     };
 
-    let cache = self::cache::get(&url)?;
+    fetch_url(ident.site, url).await
+}
+
+/// Reads a cache entry off the executor, since it decodes the whole body.
+async fn cache_get(url: &str) -> AnyResult<Option<CacheEntry>> {
+    let url = url.to_owned();
+    crate::analyze::blocking(move || self::cache::get(&url)).await?
+}
+
+/// Stores a cache entry off the executor, since it encodes the whole body, and hands it back.
+async fn cache_set(url: &str, entry: CacheEntry) -> AnyResult<CacheEntry> {
+    let url = url.to_owned();
+    crate::analyze::blocking(move || self::cache::set(&url, &entry).map(|()| entry)).await?
+}
+
+async fn fetch_url(site: Site, url: String) -> AnyResult<IVec> {
+    let cached = cache_get(&url).await?;
 
     let mut request = GLOBAL_CLIENT.get(&url);
-    if let Some((etag, _)) = &cache {
-        request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+    if let Some(cached) = &cached {
+        if cached.is_fresh() {
+            trace!("fresh cache: {}", url);
+            crate::metrics::HTTP_CACHE.hit();
+            if cached.not_found {
+                return Err(NotFound(url).into());
+            }
+            return Ok(cached.body.as_slice().into());
+        }
+        if let Some(etag) = &cached.etag {
+            request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &cached.last_modified {
+            request = request.header(reqwest::header::IF_MODIFIED_SINCE, last_modified);
+        }
     }
 
+    let _permit = HOST_LIMITS[site as usize].acquire().await;
     trace!("fetching {}", url);
-    let response: Response = request.send().await?.error_for_status()?;
-    let new_etag = match response.headers().get(reqwest::header::ETAG) {
-        None => (None),
-        Some(x) => Some(x.to_str()?.to_string()),
+    // only requests actually sent are counted, cached responses aren't
+    let response = request.send().await;
+    let success = match &response {
+        Ok(x) => {
+            let status = x.status();
+            status == reqwest::StatusCode::NOT_FOUND
+                || !(status.is_client_error() || status.is_server_error())
+        }
+        Err(_) => false,
     };
-
-    if response.status().is_success() {
-        let data: IVec = response.bytes().await?.bytes().into();
-        if let Some(new_etag) = new_etag {
-            trace!("fresh cache: {}", url);
-            self::cache::set(url, data.clone(), new_etag)?;
+    crate::metrics::observe_upstream(site, success);
+    let response: Response = response?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        crate::metrics::HTTP_CACHE.miss();
+        if let Some(entry) = CacheEntry::not_found(response.headers()) {
+            trace!("store missing: {}", url);
+            cache_set(&url, entry).await?;
         }
-        return Ok(data);
+        return Err(NotFound(url).into());
     }
+    let response = response.error_for_status()?;
 
-    if response.status().as_u16() == 304 {
+    if response.status() == reqwest::StatusCode::NOT_MODIFIED {
         trace!("resource not modified: {}", url);
-        let (_, data) =
-            cache.ok_or_else(|| anyhow::Error::msg(format!("304 without conditional: {}", url)))?;
+        let mut cached = cached
+            .ok_or_else(|| anyhow::Error::msg(format!("304 without conditional: {}", url)))?;
+        crate::metrics::HTTP_CACHE.hit();
+        if cached.revalidated(response.headers()) {
+            cached = cache_set(&url, cached).await?;
+        }
+        return Ok(cached.body.into());
+    }
+
+    if response.status().is_success() {
+        crate::metrics::HTTP_CACHE.miss();
+        let headers = response.headers().clone();
+        let data: IVec = response.bytes().await?.bytes().into();
+        if let Some(entry) = CacheEntry::new(&headers, data.to_vec()) {
+            trace!("store cache: {}", url);
+            cache_set(&url, entry).await?;
+        }
         return Ok(data);
     }
 
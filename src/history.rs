@@ -0,0 +1,114 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use sled::Tree;
+
+use crate::{model::Status, utils::AnyResult};
+
+/// page path -> `HistoryEntry`
+static HISTORY_DB: Lazy<Tree> =
+    Lazy::new(|| crate::command::database().open_tree("history").unwrap());
+
+/// approximate number of entries in `HISTORY_DB`
+static HISTORY_COUNT: Lazy<AtomicUsize> = Lazy::new(|| AtomicUsize::new(HISTORY_DB.len()));
+
+/// entries beyond this are dropped, the least recently seen first
+const HISTORY_LIMIT: usize = 10240;
+
+/// A repo or crate analyzed before, with its status at that time.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HistoryEntry {
+    /// page path without leading slash, e.g. `repo/github/owner/repo`
+    pub path: String,
+    pub title: String,
+    pub hits: u64,
+    pub last_seen: i64,
+    pub message: String,
+    pub color: String,
+}
+
+pub fn init() -> AnyResult {
+    Lazy::force(&HISTORY_COUNT);
+    Ok(())
+}
+
+/// Record an analysis on the blocking pool without waiting for it, failures are logged only
+/// since history is best effort.
+pub fn record(path: String, title: String, status: &Status) {
+    let (message, color) = status.message();
+    actix_rt::spawn(async move {
+        let result =
+            crate::analyze::blocking(move || write_entry(&path, title, message, color)).await;
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => error!("failed to record history: {:?}", e),
+            Err(e) => error!("failed to record history: {}", e),
+        }
+    });
+}
+
+fn write_entry(path: &str, title: String, message: String, color: &str) -> AnyResult {
+    let now = Utc::now().timestamp();
+    let old = HISTORY_DB.fetch_and_update(path.as_bytes(), |old| {
+        let hits = old
+            .and_then(|x| bincode::deserialize::<HistoryEntry>(x).ok())
+            .map_or(0, |x| x.hits);
+        let entry = HistoryEntry {
+            path: path.to_string(),
+            title: title.clone(),
+            hits: hits + 1,
+            last_seen: now,
+            message: message.clone(),
+            color: color.to_string(),
+        };
+        bincode::serialize(&entry).ok()
+    })?;
+    if old.is_none() {
+        let count = HISTORY_COUNT.fetch_add(1, Ordering::Relaxed) + 1;
+        if count > HISTORY_LIMIT {
+            trim()?;
+        }
+    }
+    Ok(())
+}
+
+/// Drop the least recently seen entries till only 90% of the limit is used.
+fn trim() -> AnyResult {
+    let mut entries = vec![];
+    for item in HISTORY_DB.iter() {
+        let (key, value) = item?;
+        let last_seen = bincode::deserialize::<HistoryEntry>(&value)
+            .map(|x| x.last_seen)
+            .unwrap_or(i64::min_value());
+        entries.push((last_seen, key));
+    }
+    entries.sort_by_key(|(last_seen, _)| *last_seen);
+
+    let evict = entries.len().saturating_sub(HISTORY_LIMIT * 9 / 10);
+    for (_, key) in &entries[..evict] {
+        HISTORY_DB.remove(key)?;
+    }
+    HISTORY_COUNT.store(entries.len() - evict, Ordering::Relaxed);
+    debug!("trimmed {} history entries", evict);
+    Ok(())
+}
+
+/// The most recently analyzed and the most popular entries, at most `limit` of each.
+pub fn recent_and_popular(limit: usize) -> AnyResult<(Vec<HistoryEntry>, Vec<HistoryEntry>)> {
+    let mut entries = vec![];
+    for item in HISTORY_DB.iter() {
+        let (_, value) = item?;
+        match bincode::deserialize::<HistoryEntry>(&value) {
+            Ok(x) => entries.push(x),
+            Err(e) => error!("broken history entry: {:?}", e),
+        }
+    }
+
+    entries.sort_by(|a, b| b.last_seen.cmp(&a.last_seen));
+    let recent = entries.iter().take(limit).cloned().collect();
+    entries.sort_by(|a, b| b.hits.cmp(&a.hits));
+    entries.truncate(limit);
+
+    Ok((recent, entries))
+}
@@ -1,32 +1,327 @@
-use fern::colors::{Color, ColoredLevelConfig};
-use log::LevelFilter;
+use std::{
+    cell::RefCell,
+    fmt::Arguments,
+    fs::{File, OpenOptions},
+    future::Future,
+    io::Write,
+    path::PathBuf,
+    pin::Pin,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+
+use fern::{
+    colors::{Color, ColoredLevelConfig},
+    FormatCallback,
+};
+use log::{LevelFilter, Record};
 
 pub fn init_logger() -> anyhow::Result<()> {
-    fern::Dispatch::new()
-        .format(|out, message, record| {
-            let color_config = ColoredLevelConfig::new()
-                .info(Color::Green)
-                .debug(Color::Magenta);
+    let filter = crate::command::log_filter();
+    let mut dispatch = fern::Dispatch::new().level(filter.default);
+    for (module, level) in &filter.modules {
+        dispatch = dispatch.level_for(module.clone(), *level);
+    }
+
+    let format = crate::command::log_format();
+    dispatch = dispatch.chain(
+        fern::Dispatch::new()
+            .format(move |out, message, record| format_record(out, message, record, format, true))
+            .chain(std::io::stdout()),
+    );
+    if let Some(path) = crate::command::log_file() {
+        let file = RotatingFile::open(
+            path.clone(),
+            crate::command::log_file_size(),
+            crate::command::log_file_keep(),
+        )?;
+        dispatch = dispatch.chain(
+            fern::Dispatch::new()
+                .format(move |out, message, record| {
+                    format_record(out, message, record, format, false)
+                })
+                .chain(Box::new(file) as Box<dyn Write + Send>),
+        );
+    }
+
+    dispatch.apply()?;
+    Ok(())
+}
+
+/// Levels of all modules and overrides of some, parsed from `RUST_LOG` style directives such as
+/// `info,deps_rs::fetch=trace,sled=warn`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogFilter {
+    pub default: LevelFilter,
+    pub modules: Vec<(String, LevelFilter)>,
+}
+
+impl FromStr for LogFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let mut filter = LogFilter {
+            default: LevelFilter::Info,
+            modules: vec![],
+        };
+        for directive in input.split(',').map(str::trim).filter(|x| !x.is_empty()) {
+            let mut parts = directive.splitn(2, '=');
+            let name = parts.next().unwrap();
+            match (parts.next(), name.parse::<LevelFilter>()) {
+                (None, Ok(level)) => filter.default = level,
+                // a bare module name turns on everything of it
+                (None, Err(_)) => filter.modules.push((name.to_string(), LevelFilter::Trace)),
+                (Some(level), _) => {
+                    let level = level
+                        .parse()
+                        .map_err(|_| anyhow!("invalid log level: {}", level))?;
+                    filter.modules.push((name.to_string(), level));
+                }
+            }
+        }
+        Ok(filter)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    /// human readable, colored on stdout
+    Text,
+    /// one JSON object per line
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => bail!("unknown log format: {}, expect text or json", input),
+        }
+    }
+}
+
+fn format_record(
+    out: FormatCallback,
+    message: &Arguments,
+    record: &Record,
+    format: LogFormat,
+    colored: bool,
+) {
+    let context = CURRENT.with(|x| x.borrow().clone());
+    match format {
+        LogFormat::Text => {
+            let level = if colored {
+                ColoredLevelConfig::new()
+                    .info(Color::Green)
+                    .debug(Color::Magenta)
+                    .color(record.level())
+                    .to_string()
+            } else {
+                record.level().to_string()
+            };
+            let request = context.map_or(String::new(), |x| format!("<{}> ", x.id));
             out.finish(format_args!(
-                "{} {} [{}:{}] {}",
+                "{} {} [{}:{}] {}{}",
                 chrono::Local::now().format("%F %H:%M:%S %:z"),
-                color_config.color(record.level()),
+                level,
                 record.target(),
-                record.line().unwrap(),
+                record.line().unwrap_or_default(),
+                request,
                 message
             ))
+        }
+        LogFormat::Json => {
+            let mut line = serde_json::json!({
+                "time": chrono::Utc::now().to_rfc3339(),
+                "level": record.level().to_string(),
+                "target": record.target(),
+                "line": record.line(),
+                "message": message.to_string(),
+            });
+            if let Some(context) = context {
+                line["request_id"] = context.id.clone().into();
+                if let Some((site, owner, repo)) = &context.repo {
+                    line["site"] = site.clone().into();
+                    line["owner"] = owner.clone().into();
+                    line["repo"] = repo.clone().into();
+                }
+            }
+            out.finish(format_args!("{}", line))
+        }
+    }
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<Arc<RequestContext>>> = RefCell::new(None);
+}
+
+/// Fields attached to every log line written while serving a request.
+#[derive(Debug)]
+pub struct RequestContext {
+    pub id: String,
+    /// site, owner and repo of repo routes
+    pub repo: Option<(String, String, String)>,
+}
+
+impl RequestContext {
+    /// Use `X-Request-Id` from client if it looks sane, generate one otherwise.
+    pub fn new(request_id: Option<&str>, path: &str) -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+        let id = match request_id {
+            Some(x)
+                if !x.is_empty()
+                    && x.len() <= 64
+                    && x.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') =>
+            {
+                x.to_string()
+            }
+            _ => format!(
+                "{:x}-{:x}",
+                *PROCESS_ID,
+                NEXT_ID.fetch_add(1, Ordering::Relaxed)
+            ),
+        };
+        let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+        let repo = match segments.as_slice() {
+            ["repo", site, owner, repo, ..] => Some((
+                site.to_string(),
+                owner.replace("%2F", "/"),
+                repo.to_string(),
+            )),
+            _ => None,
+        };
+        RequestContext { id, repo }
+    }
+}
+
+/// distinguishes generated request IDs of different runs
+static PROCESS_ID: once_cell::sync::Lazy<u32> =
+    once_cell::sync::Lazy::new(|| chrono::Utc::now().timestamp() as u32);
+
+/// Run `future` with `context` attached to log lines, it's restored on every poll since futures
+/// of different requests interleave on the same thread.
+pub fn with_context<F: Future>(context: RequestContext, future: F) -> WithContext<F> {
+    WithContext {
+        context: Arc::new(context),
+        inner: Box::pin(future),
+    }
+}
+
+pub struct WithContext<F> {
+    context: Arc<RequestContext>,
+    inner: Pin<Box<F>>,
+}
+
+impl<F: Future> Future for WithContext<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let previous = CURRENT.with(|x| x.replace(Some(self.context.clone())));
+        let result = self.inner.as_mut().poll(cx);
+        CURRENT.with(|x| *x.borrow_mut() = previous);
+        result
+    }
+}
+
+/// Log file renamed to `{path}.1`, `{path}.2` and so on once it grows too large, the oldest
+/// beyond `keep` are removed.
+struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    keep: usize,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_size: u64, keep: usize) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            path,
+            max_size,
+            keep,
+            file,
+            size,
         })
-        .level(LevelFilter::Info)
-        .level_for(module_path!().splitn(2, "::").next().unwrap(), log_level())
-        .chain(std::io::stdout())
-        .apply()?;
-    Ok(())
+    }
+
+    fn rotated_path(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", n));
+        path.into()
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        if self.keep == 0 {
+            self.file.set_len(0)?;
+        } else {
+            let _ = std::fs::remove_file(self.rotated_path(self.keep));
+            for n in (1..self.keep).rev() {
+                let from = self.rotated_path(n);
+                if from.exists() {
+                    std::fs::rename(from, self.rotated_path(n + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, self.rotated_path(1))?;
+            self.file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+        }
+        self.size = 0;
+        Ok(())
+    }
 }
 
-fn log_level() -> LevelFilter {
-    if cfg!(debug_assertions) {
-        LevelFilter::Trace
-    } else {
-        LevelFilter::Info
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
     }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[test]
+fn test_log_filter_parse() {
+    let filter: LogFilter = "warn,deps_rs::fetch=trace,sled".parse().unwrap();
+    assert_eq!(filter.default, LevelFilter::Warn);
+    assert_eq!(
+        filter.modules,
+        vec![
+            ("deps_rs::fetch".to_string(), LevelFilter::Trace),
+            ("sled".to_string(), LevelFilter::Trace),
+        ]
+    );
+    assert!("deps_rs=loud".parse::<LogFilter>().is_err());
+}
+
+#[test]
+fn test_request_context() {
+    let context = RequestContext::new(Some("abc-123"), "/repo/gitlab/group%2Fsub/project");
+    assert_eq!(context.id, "abc-123");
+    assert_eq!(
+        context.repo,
+        Some((
+            "gitlab".to_string(),
+            "group/sub".to_string(),
+            "project".to_string()
+        ))
+    );
+    assert_ne!(RequestContext::new(Some("a b"), "/").id, "a b");
 }
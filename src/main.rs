@@ -9,13 +9,20 @@ extern crate log;
 #[macro_use]
 extern crate serde;
 
+use actix_web::{
+    dev::Service,
+    http::header::{HeaderName, HeaderValue},
+};
+
 use crate::utils::AnyResult;
 
 mod analyze;
 mod command;
 mod database;
 mod fetch;
+mod history;
 mod logger;
+mod metrics;
 mod model;
 mod parser;
 mod utils;
@@ -28,12 +35,59 @@ async fn main() -> AnyResult {
     actix_web::HttpServer::new(|| {
         actix_web::App::new()
             .wrap(actix_web::middleware::NormalizePath)
-            .wrap(actix_web::middleware::Logger::default())
-            .service(actix_files::Files::new("/static", "./static"))
+            .wrap_fn(|req, srv| {
+                let route = crate::metrics::route_label(req.path());
+                let begin = std::time::Instant::now();
+                let response = srv.call(req);
+                async move {
+                    let response = response.await;
+                    crate::metrics::observe_request(route, begin.elapsed());
+                    response
+                }
+            })
+            .wrap_fn(|req, srv| {
+                let context = crate::logger::RequestContext::new(
+                    req.headers()
+                        .get("x-request-id")
+                        .and_then(|x| x.to_str().ok()),
+                    req.path(),
+                );
+                let request_id = HeaderValue::from_str(&context.id);
+                let response = crate::logger::with_context(context, srv.call(req));
+                async move {
+                    let mut response = response.await?;
+                    if let Ok(request_id) = request_id {
+                        response
+                            .headers_mut()
+                            .insert(HeaderName::from_static("x-request-id"), request_id);
+                    }
+                    Ok::<_, actix_web::Error>(response)
+                }
+            })
+            // registered last to be the outermost, so it sees `x-request-id` set above
+            .wrap(actix_web::middleware::Logger::new(
+                r#"%a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T %{x-request-id}o"#,
+            ))
+            .service(crate::view::assets::static_file)
+            .service(crate::view::index::index)
+            .service(crate::view::index::search)
+            .service(crate::view::index::go)
             .service(crate::view::status::repo_svg)
+            .service(crate::view::status::repo_member_svg)
+            .service(crate::view::status::repo_shields)
+            .service(crate::view::status::repo_json)
             .service(crate::view::status::repo_html)
+            .service(crate::view::status::crate_latest_svg)
+            .service(crate::view::status::crate_latest_shields)
+            .service(crate::view::status::crate_latest_json)
             .service(crate::view::status::crate_svg)
+            .service(crate::view::status::crate_shields)
+            .service(crate::view::status::crate_json)
+            .service(crate::view::status::crate_latest_html)
             .service(crate::view::status::crate_html)
+            .service(crate::view::health::healthz)
+            .service(crate::view::health::readyz)
+            .service(crate::view::metrics::metrics)
     })
     .bind("127.0.0.1:8000")?
     .run()
@@ -43,11 +97,11 @@ async fn main() -> AnyResult {
 }
 
 fn init() -> AnyResult {
-    crate::logger::init_logger()?;
     crate::command::init()?;
+    crate::logger::init_logger()?;
     crate::fetch::init()?;
-    crate::view::init()?;
     crate::database::init()?;
+    crate::history::init()?;
 
     Ok(())
 }
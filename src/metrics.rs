@@ -0,0 +1,297 @@
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicI64, AtomicU64, Ordering},
+    time::Duration,
+};
+
+use chrono::Utc;
+use once_cell::sync::Lazy;
+
+use crate::model::Site;
+
+/// Route labels, see `route_label`.
+const ROUTES: &[&str] = &[
+    "index",
+    "search",
+    "go",
+    "static",
+    "metrics",
+    "health",
+    "repo_svg",
+    "repo_shields",
+    "repo_json",
+    "repo_html",
+    "repo_member_svg",
+    "crate_svg",
+    "crate_shields",
+    "crate_json",
+    "crate_html",
+    "other",
+];
+
+const SITES: &[Site] = &[Site::GitHub, Site::GitLab, Site::BitBucket];
+
+/// upper bounds of latency buckets, in seconds
+const BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+static REQUESTS: Lazy<Vec<Histogram>> =
+    Lazy::new(|| ROUTES.iter().map(|_| Histogram::default()).collect());
+
+pub static ANALYZE_CACHE: HitRate = HitRate::new();
+pub static HTTP_CACHE: HitRate = HitRate::new();
+
+/// indexed by `Site as usize`
+static UPSTREAM_FETCHES: [AtomicU64; 3] = [ATOMIC_ZERO, ATOMIC_ZERO, ATOMIC_ZERO];
+static UPSTREAM_ERRORS: [AtomicU64; 3] = [ATOMIC_ZERO, ATOMIC_ZERO, ATOMIC_ZERO];
+#[allow(clippy::declare_interior_mutable_const)]
+const ATOMIC_ZERO: AtomicU64 = AtomicU64::new(0);
+
+static HTTP_CACHE_BYTES: AtomicU64 = AtomicU64::new(0);
+static HTTP_CACHE_EVICTIONS: AtomicU64 = AtomicU64::new(0);
+
+static INDEX_REFRESH_MICROS: AtomicU64 = AtomicU64::new(0);
+static INDEX_LAST_SUCCESS: AtomicI64 = AtomicI64::new(0);
+
+#[derive(Default)]
+struct Histogram {
+    /// not cumulative, the last one is `+Inf`
+    buckets: [AtomicU64; 12],
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        let i = BUCKETS
+            .iter()
+            .position(|x| seconds <= *x)
+            .unwrap_or_else(|| BUCKETS.len());
+        self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+pub struct HitRate {
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl HitRate {
+    const fn new() -> Self {
+        HitRate {
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn hit(&self) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn miss(&self) {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Bounded label of request path, named after the handler serving it.
+pub fn route_label(path: &str) -> &'static str {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let suffix = match segments.last().copied().unwrap_or_default() {
+        "status.svg" => "svg",
+        "shields.json" => "shields",
+        "status.json" => "json",
+        _ => "html",
+    };
+    match (segments[0], suffix) {
+        ("", _) => "index",
+        ("search", _) => "search",
+        ("go", _) => "go",
+        ("static", _) => "static",
+        ("metrics", _) => "metrics",
+        ("healthz", _) | ("readyz", _) => "health",
+        ("repo", "svg") if segments.len() > 5 => "repo_member_svg",
+        ("repo", "svg") => "repo_svg",
+        ("repo", "shields") => "repo_shields",
+        ("repo", "json") => "repo_json",
+        ("repo", _) => "repo_html",
+        ("crate", "svg") => "crate_svg",
+        ("crate", "shields") => "crate_shields",
+        ("crate", "json") => "crate_json",
+        ("crate", _) => "crate_html",
+        _ => "other",
+    }
+}
+
+pub fn observe_request(route: &str, elapsed: Duration) {
+    let i = ROUTES
+        .iter()
+        .position(|x| *x == route)
+        .unwrap_or(ROUTES.len() - 1);
+    REQUESTS[i].observe(elapsed);
+}
+
+pub fn observe_upstream(site: Site, success: bool) {
+    let i = site as usize;
+    UPSTREAM_FETCHES[i].fetch_add(1, Ordering::Relaxed);
+    if !success {
+        UPSTREAM_ERRORS[i].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+pub fn set_http_cache_bytes(bytes: u64) {
+    HTTP_CACHE_BYTES.store(bytes, Ordering::Relaxed);
+}
+
+pub fn observe_http_cache_evictions(count: u64) {
+    HTTP_CACHE_EVICTIONS.fetch_add(count, Ordering::Relaxed);
+}
+
+pub fn observe_index_refresh(elapsed: Duration) {
+    INDEX_REFRESH_MICROS.store(elapsed.as_micros() as u64, Ordering::Relaxed);
+    INDEX_LAST_SUCCESS.store(Utc::now().timestamp(), Ordering::Relaxed);
+}
+
+/// All metrics in Prometheus text exposition format.
+pub fn render() -> String {
+    let mut out = String::new();
+    let load = |x: &AtomicU64| x.load(Ordering::Relaxed);
+
+    out.push_str("# HELP deps_http_request_duration_seconds Latency of HTTP requests.\n");
+    out.push_str("# TYPE deps_http_request_duration_seconds histogram\n");
+    for (route, histogram) in ROUTES.iter().zip(REQUESTS.iter()) {
+        let mut count = 0;
+        for (i, bucket) in histogram.buckets.iter().enumerate() {
+            count += load(bucket);
+            let le = BUCKETS.get(i).map_or("+Inf".to_string(), |x| x.to_string());
+            writeln!(
+                out,
+                "deps_http_request_duration_seconds_bucket{{route=\"{}\",le=\"{}\"}} {}",
+                route, le, count
+            )
+            .unwrap();
+        }
+        let sum = load(&histogram.sum_micros) as f64 / 1e6;
+        writeln!(
+            out,
+            "deps_http_request_duration_seconds_sum{{route=\"{}\"}} {}",
+            route, sum
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "deps_http_request_duration_seconds_count{{route=\"{}\"}} {}",
+            route, count
+        )
+        .unwrap();
+    }
+
+    out.push_str("# HELP deps_cache_requests_total Lookups of in-process caches.\n");
+    out.push_str("# TYPE deps_cache_requests_total counter\n");
+    for (cache, rate) in &[("analyze", &ANALYZE_CACHE), ("http", &HTTP_CACHE)] {
+        for (result, value) in &[("hit", &rate.hits), ("miss", &rate.misses)] {
+            writeln!(
+                out,
+                "deps_cache_requests_total{{cache=\"{}\",result=\"{}\"}} {}",
+                cache,
+                result,
+                load(value)
+            )
+            .unwrap();
+        }
+    }
+
+    out.push_str("# HELP deps_http_cache_bytes Size of cached upstream responses.\n");
+    out.push_str("# TYPE deps_http_cache_bytes gauge\n");
+    writeln!(out, "deps_http_cache_bytes {}", load(&HTTP_CACHE_BYTES)).unwrap();
+    out.push_str("# HELP deps_http_cache_evictions_total Responses evicted for size limit.\n");
+    out.push_str("# TYPE deps_http_cache_evictions_total counter\n");
+    writeln!(
+        out,
+        "deps_http_cache_evictions_total {}",
+        load(&HTTP_CACHE_EVICTIONS)
+    )
+    .unwrap();
+
+    out.push_str("# HELP deps_upstream_fetches_total Files fetched from code hosting sites.\n");
+    out.push_str("# TYPE deps_upstream_fetches_total counter\n");
+    for site in SITES {
+        let value = load(&UPSTREAM_FETCHES[*site as usize]);
+        writeln!(
+            out,
+            "deps_upstream_fetches_total{{site=\"{}\"}} {}",
+            site.name(),
+            value
+        )
+        .unwrap();
+    }
+    out.push_str("# HELP deps_upstream_errors_total Failed fetches, missing files excluded.\n");
+    out.push_str("# TYPE deps_upstream_errors_total counter\n");
+    for site in SITES {
+        let value = load(&UPSTREAM_ERRORS[*site as usize]);
+        writeln!(
+            out,
+            "deps_upstream_errors_total{{site=\"{}\"}} {}",
+            site.name(),
+            value
+        )
+        .unwrap();
+    }
+
+    let index_refresh = load(&INDEX_REFRESH_MICROS) as f64 / 1e6;
+    let last_success = INDEX_LAST_SUCCESS.load(Ordering::Relaxed);
+    out.push_str("# HELP deps_index_refresh_duration_seconds Duration of last index refresh.\n");
+    out.push_str("# TYPE deps_index_refresh_duration_seconds gauge\n");
+    writeln!(out, "deps_index_refresh_duration_seconds {}", index_refresh).unwrap();
+    out.push_str("# HELP deps_index_crates Crates loaded from index.\n");
+    out.push_str("# TYPE deps_index_crates gauge\n");
+    writeln!(out, "deps_index_crates {}", crate::database::crate_count()).unwrap();
+    out.push_str("# HELP deps_index_last_success_timestamp_seconds Last successful refresh.\n");
+    out.push_str("# TYPE deps_index_last_success_timestamp_seconds gauge\n");
+    writeln!(
+        out,
+        "deps_index_last_success_timestamp_seconds {}",
+        last_success
+    )
+    .unwrap();
+
+    if let Some(last_success) = crate::database::audit_state().last_success {
+        let age = (Utc::now() - last_success).num_seconds();
+        out.push_str("# HELP deps_advisory_db_age_seconds Time since advisory database loaded.\n");
+        out.push_str("# TYPE deps_advisory_db_age_seconds gauge\n");
+        writeln!(out, "deps_advisory_db_age_seconds {}", age).unwrap();
+    }
+
+    out
+}
+
+#[test]
+fn test_route_label() {
+    assert_eq!(route_label("/"), "index");
+    assert_eq!(route_label("/repo/github/owner/repo"), "repo_html");
+    assert_eq!(
+        route_label("/repo/github/owner/repo/status.svg"),
+        "repo_svg"
+    );
+    assert_eq!(
+        route_label("/repo/github/owner/repo/crate/member/status.svg"),
+        "repo_member_svg"
+    );
+    assert_eq!(
+        route_label("/crate/serde/1.0.0/shields.json"),
+        "crate_shields"
+    );
+    assert_eq!(route_label("/crate/serde"), "crate_html");
+    assert_eq!(route_label("/wp-admin"), "other");
+}
+
+#[test]
+fn test_histogram() {
+    let histogram = Histogram::default();
+    histogram.observe(Duration::from_millis(1));
+    histogram.observe(Duration::from_secs(60));
+    assert_eq!(histogram.buckets[0].load(Ordering::Relaxed), 1);
+    assert_eq!(histogram.buckets[BUCKETS.len()].load(Ordering::Relaxed), 1);
+}
@@ -1,7 +1,7 @@
 use std::{iter::Sum, ops::Add};
 
 use badge::{Badge, BadgeOptions};
-use semver::Version;
+use semver::{Version, VersionReq};
 
 #[derive(Debug, Deserialize, Eq, PartialEq, Hash, Clone, Copy)]
 #[serde(rename_all = "lowercase")]
@@ -20,14 +20,6 @@ impl Site {
         }
     }
 
-    pub fn site_icon(self) -> &'static str {
-        match self {
-            Site::GitHub => "fa-github",
-            Site::GitLab => "fa-gitlab",
-            Site::BitBucket => "fa-bitbucket",
-        }
-    }
-
     pub fn base_uri(self) -> &'static str {
         match self {
             Site::GitHub => "https://github.com",
@@ -35,13 +27,196 @@ impl Site {
             Site::BitBucket => "https://bitbucket.org",
         }
     }
+
+    pub fn from_host(host: &str) -> Option<Site> {
+        match host.trim_start_matches("www.") {
+            "github.com" => Some(Site::GitHub),
+            "gitlab.com" => Some(Site::GitLab),
+            "bitbucket.org" => Some(Site::BitBucket),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Eq, PartialEq, Hash, Clone)]
 pub struct RepoIdentity {
+    pub site: Site,
+    /// could contain `/` for nested GitLab groups
+    pub owner: String,
+    pub repo: String,
+    /// branch, tag or commit, `HEAD` if omitted
+    #[serde(default)]
+    pub git_ref: Option<String>,
+    /// directory of the root manifest, relative to repo root
+    #[serde(default)]
+    pub path: Option<String>,
+}
+
+/// Query parameters of repo routes, pointing into the repo.
+#[derive(Debug, Deserialize, Default)]
+pub struct RepoLocation {
+    #[serde(rename = "ref")]
+    pub git_ref: Option<String>,
+    pub path: Option<String>,
+}
+
+impl RepoIdentity {
+    /// Apply location from query, and decode nested groups given as `%2F` in owner. `None` if
+    /// ref or path could point out of this repo, or out of the URL path when fetching.
+    pub fn locate(mut self, location: &RepoLocation) -> Option<Self> {
+        self.owner = self.owner.replace("%2F", "/").replace("%2f", "/");
+        let non_empty = |x: &Option<String>| {
+            x.as_deref()
+                .map(|x| x.trim_matches('/'))
+                .filter(|x| !x.is_empty())
+                .map(String::from)
+        };
+        self.git_ref = non_empty(&location.git_ref);
+        self.path = non_empty(&location.path);
+
+        let is_valid = |x: &Option<String>| {
+            x.as_deref().map_or(true, |x| {
+                !x.contains("..") && !x.contains(|c| matches!(c, '<' | '>' | '?' | '#'))
+            })
+        };
+        if is_valid(&self.git_ref) && is_valid(&self.path) {
+            Some(self)
+        } else {
+            None
+        }
+    }
+
+    /// Path of this repo's page, without leading slash and query.
+    pub fn path(&self) -> String {
+        format!(
+            "repo/{}/{}/{}",
+            self.site.name(),
+            self.owner.replace('/', "%2F"),
+            self.repo
+        )
+    }
+
+    /// Query string of ref and path, empty or starting with `?`.
+    pub fn query(&self) -> String {
+        let mut serializer = url::form_urlencoded::Serializer::new(String::new());
+        if let Some(git_ref) = &self.git_ref {
+            serializer.append_pair("ref", git_ref);
+        }
+        if let Some(path) = &self.path {
+            serializer.append_pair("path", path);
+        }
+        match serializer.finish() {
+            x if x.is_empty() => x,
+            x => format!("?{}", x),
+        }
+    }
+
+    pub fn title(&self) -> String {
+        let mut title = format!("{}/{}", self.owner, self.repo);
+        if let Some(git_ref) = &self.git_ref {
+            title = format!("{}@{}", title, git_ref);
+        }
+        if let Some(path) = &self.path {
+            title = format!("{}:{}", title, path);
+        }
+        title
+    }
+
+    pub fn source_url(&self) -> String {
+        format!("{}/{}/{}", self.site.base_uri(), self.owner, self.repo)
+    }
+
+    /// Parse repository URL in common forms, such as
+    /// `https://github.com/owner/repo/tree/ref/path`, `git@gitlab.com:group/subgroup/repo.git`
+    /// or `bitbucket.org/owner/repo/src/ref/path`.
+    pub fn parse_url(input: &str) -> Option<RepoIdentity> {
+        let input = input.trim();
+        let input = input.split(|c| c == '?' || c == '#').next()?;
+        let (host, path) = match input.find("://") {
+            Some(i) => {
+                let rest = &input[i + 3..];
+                let i = rest.find('/')?;
+                (&rest[..i], &rest[i + 1..])
+            }
+            // scp-like syntax of ssh
+            None if input.starts_with("git@") => {
+                let i = input.find(':')?;
+                (&input[..i], &input[i + 1..])
+            }
+            None => {
+                let i = input.find('/')?;
+                (&input[..i], &input[i + 1..])
+            }
+        };
+        let host = host.rsplit('@').next()?;
+        let host = host.split(':').next()?;
+        let site = Site::from_host(host)?;
+
+        let segments: Vec<&str> = path.split('/').filter(|x| !x.is_empty()).collect();
+        // where repo path ends, and what follows it
+        let (repo_len, rest) = match site {
+            Site::GitHub | Site::BitBucket => {
+                let i = 2.min(segments.len());
+                (i, &segments[i..])
+            }
+            Site::GitLab => {
+                let i = segments
+                    .iter()
+                    .position(|x| *x == "-")
+                    .unwrap_or_else(|| segments.len());
+                (i, &segments[(i + 1).min(segments.len())..])
+            }
+        };
+        let is_name = |x: &&str| {
+            x.chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+        };
+        if repo_len < 2 || !segments[..repo_len].iter().all(is_name) {
+            return None;
+        }
+        let owner = segments[..repo_len - 1].join("/");
+        let repo = segments[repo_len - 1].trim_end_matches(".git").to_string();
+
+        let (git_ref, mut dir) = match rest {
+            [kind, git_ref, dir @ ..] if ["tree", "blob", "src"].contains(kind) => {
+                (Some(git_ref.to_string()), dir.to_vec())
+            }
+            _ => (None, vec![]),
+        };
+        if dir.last().map_or(false, |x| x.ends_with(".toml")) {
+            dir.pop();
+        }
+        let path = Some(dir.join("/")).filter(|x| !x.is_empty());
+
+        Some(RepoIdentity {
+            site,
+            owner,
+            repo,
+            git_ref,
+            path,
+        })
+    }
+}
+
+/// A member crate of a workspace repo.
+#[derive(Debug, Deserialize)]
+pub struct RepoMemberIdentity {
     pub site: Site,
     pub owner: String,
     pub repo: String,
+    pub member: String,
+}
+
+impl RepoMemberIdentity {
+    pub fn repo(&self) -> RepoIdentity {
+        RepoIdentity {
+            site: self.site,
+            owner: self.owner.clone(),
+            repo: self.repo.clone(),
+            git_ref: None,
+            path: None,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -50,11 +225,111 @@ pub struct CrateIdentity {
     pub version: Version,
 }
 
+/// A crate as given in routes, version could be omitted, `latest`, exact or a requirement.
+#[derive(Debug, Deserialize, Clone)]
+pub struct CrateSpec {
+    pub name: String,
+    #[serde(default)]
+    pub version: Option<String>,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum VersionSpec {
+    /// newest non-yanked, non-prerelease version
+    Latest,
+    Exact(Version),
+    /// newest non-yanked version matching the requirement
+    Req(VersionReq),
+}
+
+impl CrateSpec {
+    pub fn version_spec(&self) -> Option<VersionSpec> {
+        let version = match self.version.as_deref() {
+            None | Some("latest") => return Some(VersionSpec::Latest),
+            Some(x) => x,
+        };
+        if let Ok(x) = version.parse() {
+            return Some(VersionSpec::Exact(x));
+        }
+        version.parse().ok().map(VersionSpec::Req)
+    }
+
+    /// Path of this crate's page, without leading slash.
+    pub fn path(&self) -> String {
+        match &self.version {
+            None => format!("crate/{}", self.name),
+            Some(version) => format!("crate/{}/{}", self.name, version),
+        }
+    }
+}
+
+/// How far a dependency is behind its latest release, ordered by severity.
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Clone, Copy)]
+pub enum Lag {
+    UpToDate,
+    Patch(u64),
+    Minor(u64),
+    /// breaking releases behind, minor releases count as breaking ones for `0.x` versions, and
+    /// patch releases for `0.0.x` ones
+    Major(u64),
+    /// no release matches the requirement
+    Unmatched,
+}
+
+impl Lag {
+    pub fn between(current: &Version, latest: &Version) -> Lag {
+        if latest <= current {
+            Lag::UpToDate
+        } else if latest.major != current.major {
+            Lag::Major(latest.major - current.major)
+        } else if latest.minor != current.minor {
+            if latest.major == 0 {
+                Lag::Major(latest.minor - current.minor)
+            } else {
+                Lag::Minor(latest.minor - current.minor)
+            }
+        } else if latest.major == 0 && latest.minor == 0 {
+            Lag::Major(latest.patch - current.patch)
+        } else {
+            Lag::Patch(latest.patch - current.patch)
+        }
+    }
+
+    pub fn message(self) -> String {
+        match self {
+            Lag::UpToDate => "up to date".into(),
+            Lag::Patch(_) => "patch behind".into(),
+            Lag::Minor(n) => format!("{} minor behind", n),
+            Lag::Major(n) => format!("{} major behind", n),
+            Lag::Unmatched => "no matching release".into(),
+        }
+    }
+
+    pub fn color(self) -> &'static str {
+        match self {
+            Lag::UpToDate => "#4c1",
+            Lag::Patch(_) => "#a4a61d",
+            Lag::Minor(_) => "#dfb317",
+            Lag::Major(_) => "#fe7d37",
+            Lag::Unmatched => "#e05d44",
+        }
+    }
+}
+
 #[derive(Debug, Eq, PartialEq)]
 pub enum Status {
     Unknown,
+    /// repo, crate or workspace member doesn't exist
+    NotFound,
+    /// failed to analyze
+    Error,
     Insecure,
-    Normal { total: u32, outdated: u32 },
+    Normal {
+        total: u32,
+        outdated: u32,
+        /// the most severe lag among outdated dependencies
+        lag: Lag,
+    },
 }
 
 impl Sum for Status {
@@ -78,60 +353,237 @@ impl Add for Status {
     fn add(self, rhs: Self) -> Self::Output {
         match (self, rhs) {
             (_, Status::Insecure) | (Status::Insecure, _) => Status::Insecure,
-            (Status::Unknown, _) | (_, Status::Unknown) => Status::Unknown,
             (
                 Status::Normal {
                     total: total1,
                     outdated: outdated1,
+                    lag: lag1,
                 },
                 Status::Normal {
                     total: total2,
                     outdated: outdated2,
+                    lag: lag2,
                 },
             ) => Status::Normal {
                 total: total1 + total2,
                 outdated: outdated1 + outdated2,
+                lag: lag1.max(lag2),
             },
+            (Status::Normal { .. }, other) => other,
+            (this, _) => this,
         }
     }
 }
 
 impl Status {
-    pub fn to_badge(&self) -> Badge {
-        let badge_options = match self {
-            Status::Unknown => BadgeOptions {
-                subject: "dependencies".into(),
-                status: "unknown".into(),
-                color: "#9f9f9f".into(),
-            },
-            Status::Normal { total, outdated } => {
+    /// Badge message and color.
+    pub fn message(&self) -> (String, &'static str) {
+        match self {
+            Status::Unknown => ("unknown".into(), "#9f9f9f"),
+            Status::NotFound => ("not found".into(), "#9f9f9f"),
+            Status::Error => ("error".into(), "#9f9f9f"),
+            Status::Normal {
+                total, outdated, ..
+            } => {
                 if *outdated > 0 {
-                    BadgeOptions {
-                        subject: "dependencies".into(),
-                        status: format!("{} of {} outdated", outdated, total),
-                        color: "#dfb317".into(),
-                    }
+                    (format!("{} of {} outdated", outdated, total), "#dfb317")
                 } else if *total > 0 {
-                    BadgeOptions {
-                        subject: "dependencies".into(),
-                        status: "up to date".into(),
-                        color: "#4c1".into(),
-                    }
+                    ("up to date".into(), "#4c1")
                 } else {
-                    BadgeOptions {
-                        subject: "dependencies".into(),
-                        status: "none".into(),
-                        color: "#4c1".into(),
-                    }
+                    ("none".into(), "#4c1")
                 }
             }
-            Status::Insecure => BadgeOptions {
-                subject: "dependencies".into(),
-                status: "insecure".into(),
-                color: "#e05d44".into(),
-            },
-        };
+            Status::Insecure => ("insecure".into(), "#e05d44"),
+        }
+    }
 
-        badge::Badge::new(badge_options).unwrap()
+    /// Badge message and color, only telling whether it's insecure.
+    pub fn compact_message(&self) -> (String, &'static str) {
+        match self {
+            Status::Normal { .. } => ("ok".into(), "#4c1"),
+            _ => self.message(),
+        }
     }
+
+    /// Badge message and color, telling how far behind the most outdated dependency is.
+    pub fn lag_message(&self) -> (String, &'static str) {
+        match self {
+            Status::Normal { lag, .. } => (lag.message(), lag.color()),
+            _ => self.message(),
+        }
+    }
+
+    pub fn to_badge(&self) -> Badge {
+        let (status, color) = self.message();
+        badge::Badge::new(BadgeOptions {
+            subject: "dependencies".into(),
+            status,
+            color: color.into(),
+        })
+        .unwrap()
+    }
+
+    /// Badge telling how far behind the most outdated dependency is, e.g. "2 major behind".
+    pub fn to_lag_badge(&self) -> Badge {
+        let (status, color) = self.lag_message();
+        badge::Badge::new(BadgeOptions {
+            subject: "dependencies".into(),
+            status,
+            color: color.into(),
+        })
+        .unwrap()
+    }
+}
+
+#[test]
+fn test_version_spec() {
+    let spec = |version: Option<&str>| {
+        CrateSpec {
+            name: "serde".to_string(),
+            version: version.map(|x| x.to_string()),
+        }
+        .version_spec()
+    };
+
+    assert_eq!(spec(None), Some(VersionSpec::Latest));
+    assert_eq!(spec(Some("latest")), Some(VersionSpec::Latest));
+    assert_eq!(
+        spec(Some("1.0.104")),
+        Some(VersionSpec::Exact("1.0.104".parse().unwrap()))
+    );
+    assert_eq!(
+        spec(Some("^1")),
+        Some(VersionSpec::Req("^1".parse().unwrap()))
+    );
+    assert_eq!(spec(Some("not a version")), None);
+}
+
+#[test]
+fn test_lag_between() {
+    let lag = |current: &str, latest: &str| {
+        Lag::between(&current.parse().unwrap(), &latest.parse().unwrap())
+    };
+
+    assert_eq!(lag("1.2.3", "1.2.3"), Lag::UpToDate);
+    assert_eq!(lag("1.2.3", "1.2.5"), Lag::Patch(2));
+    assert_eq!(lag("1.2.3", "1.4.0"), Lag::Minor(2));
+    assert_eq!(lag("1.2.3", "3.0.0"), Lag::Major(2));
+    assert_eq!(lag("0.2.3", "0.3.0"), Lag::Major(1));
+    assert_eq!(lag("0.2.3", "1.0.0"), Lag::Major(1));
+    assert_eq!(lag("0.0.1", "0.0.3"), Lag::Major(2));
+    assert_eq!(lag("0.0.3", "0.1.0"), Lag::Major(1));
+    assert!(Lag::Unmatched > Lag::Major(5));
+    assert!(Lag::Major(1) > Lag::Minor(5));
+    assert!(Lag::Minor(1) > Lag::Patch(5));
+
+    let unmatched = crate::analyze::AnalyzedDependency {
+        name: "foo".to_string(),
+        required: "^0.1".parse().unwrap(),
+        latest_that_matches: None,
+        latest: Some("0.3.0".parse().unwrap()),
+        latest_released_at: None,
+        advisories: vec![],
+        ignored_advisories: vec![],
+    };
+    assert_eq!(unmatched.lag(), Lag::Unmatched);
+}
+
+#[test]
+fn test_parse_repo_url() {
+    let parse = |x: &str| {
+        RepoIdentity::parse_url(x).map(|x| {
+            let RepoIdentity {
+                site,
+                owner,
+                repo,
+                git_ref,
+                path,
+            } = x;
+            (site, owner, repo, git_ref, path)
+        })
+    };
+    let some = |s, o: &str, r: &str, g: Option<&str>, p: Option<&str>| {
+        Some((
+            s,
+            o.to_string(),
+            r.to_string(),
+            g.map(String::from),
+            p.map(String::from),
+        ))
+    };
+
+    assert_eq!(
+        parse("https://github.com/owner/repo"),
+        some(Site::GitHub, "owner", "repo", None, None)
+    );
+    assert_eq!(
+        parse("git@gitlab.com:owner/repo.git"),
+        some(Site::GitLab, "owner", "repo", None, None)
+    );
+    assert_eq!(
+        parse("https://github.com/owner/repo/tree/dev/crates/foo"),
+        some(
+            Site::GitHub,
+            "owner",
+            "repo",
+            Some("dev"),
+            Some("crates/foo")
+        )
+    );
+    assert_eq!(
+        parse("https://github.com/owner/repo/blob/v1.0/sub/Cargo.toml"),
+        some(Site::GitHub, "owner", "repo", Some("v1.0"), Some("sub"))
+    );
+    assert_eq!(
+        parse("https://gitlab.com/group/subgroup/project/-/tree/main?ref_type=heads"),
+        some(
+            Site::GitLab,
+            "group/subgroup",
+            "project",
+            Some("main"),
+            None
+        )
+    );
+    assert_eq!(
+        parse("ssh://git@gitlab.com:22/group/subgroup/project.git"),
+        some(Site::GitLab, "group/subgroup", "project", None, None)
+    );
+    assert_eq!(
+        parse("bitbucket.org/owner/repo/src/master/sub/"),
+        some(
+            Site::BitBucket,
+            "owner",
+            "repo",
+            Some("master"),
+            Some("sub")
+        )
+    );
+    assert_eq!(parse("https://example.com/owner/repo"), None);
+    assert_eq!(parse("https://github.com/owner"), None);
+}
+
+#[test]
+fn test_repo_locate() {
+    let locate = |git_ref: Option<&str>, path: Option<&str>| {
+        let ident = RepoIdentity {
+            site: Site::GitLab,
+            owner: "group%2Fsub".to_string(),
+            repo: "project".to_string(),
+            git_ref: None,
+            path: None,
+        };
+        ident.locate(&RepoLocation {
+            git_ref: git_ref.map(String::from),
+            path: path.map(String::from),
+        })
+    };
+
+    let ident = locate(Some("v1.0"), Some("/crates/foo/")).unwrap();
+    assert_eq!(ident.owner, "group/sub");
+    assert_eq!(ident.git_ref.as_deref(), Some("v1.0"));
+    assert_eq!(ident.path.as_deref(), Some("crates/foo"));
+    assert!(locate(None, Some("<img src=x onerror=alert(1)>/..")).is_none());
+    assert!(locate(None, Some("../../other/repo")).is_none());
+    assert!(locate(Some("master?x=1"), None).is_none());
+    assert!(locate(Some("master#x"), None).is_none());
 }
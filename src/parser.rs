@@ -1,4 +1,7 @@
 use indexmap::map::IndexMap;
+use semver::{Version, VersionReq};
+
+use crate::database::DependencyKind;
 
 // TODO: support glob syntax in "members"
 // TODO: support platform specific dependencies
@@ -36,6 +39,173 @@ pub struct Package {
     pub name: String,
 }
 
+/// Repo side configuration, read from `.deps-rs.toml`, or `deny.toml` of cargo-deny as fallback.
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Default, Clone)]
+pub struct RepoConfig {
+    #[serde(default)]
+    pub advisories: AdvisoriesConfig,
+    #[serde(default)]
+    pub policy: Policy,
+}
+
+impl RepoConfig {
+    /// Advisory ignores from `deny.toml` of cargo-deny, entries this doesn't understand are
+    /// skipped and a broken file is ignored, since the file isn't written for deps.rs.
+    pub fn from_deny_toml(content: &[u8]) -> Self {
+        let mut config = RepoConfig::default();
+        let value = match toml::from_slice::<toml::Value>(content) {
+            Ok(x) => x,
+            Err(e) => {
+                warn!("ignored broken deny.toml: {}", e);
+                return config;
+            }
+        };
+
+        let entries = value
+            .get("advisories")
+            .and_then(|x| x.get("ignore"))
+            .and_then(|x| x.as_array());
+        for entry in entries.into_iter().flatten() {
+            let ignored = match entry {
+                toml::Value::String(id) => Some(IgnoredAdvisory::Id(id.clone())),
+                toml::Value::Table(table) => table
+                    .get("id")
+                    .and_then(|x| x.as_str())
+                    .map(|id| IgnoredAdvisory::Table { id: id.to_string() }),
+                _ => None,
+            };
+            match ignored {
+                Some(x) => config.advisories.ignore.push(x),
+                None => debug!("skipped advisory ignore of deny.toml: {}", entry),
+            }
+        }
+        config
+    }
+}
+
+/// Which outdated dependencies matter, security findings are controlled by `AdvisoriesConfig` only.
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Default, Clone)]
+pub struct Policy {
+    /// dependency kinds counted in status, all kinds if not set
+    pub kinds: Option<Vec<DependencyKind>>,
+    /// crates never counted as outdated
+    #[serde(default)]
+    pub ignore: Vec<String>,
+    /// version lines kept on purpose, e.g. `tokio = "0.2"`
+    #[serde(default)]
+    pub pinned: IndexMap<String, VersionReq>,
+}
+
+impl Policy {
+    pub fn counts_kind(&self, kind: DependencyKind) -> bool {
+        match &self.kinds {
+            None => true,
+            Some(kinds) => kinds.contains(&kind),
+        }
+    }
+
+    /// Whether an outdated dependency resolved to `version` is accepted by this policy.
+    pub fn tolerates(&self, crate_name: &str, version: Option<&Version>) -> bool {
+        if self.ignore.iter().any(|x| x == crate_name) {
+            return true;
+        }
+        match (self.pinned.get(crate_name), version) {
+            (Some(req), Some(version)) => req.matches(version),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Default, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct AdvisoriesConfig {
+    /// advisory IDs accepted after review
+    #[serde(default)]
+    pub ignore: Vec<IgnoredAdvisory>,
+    /// crates whose advisories are all accepted
+    #[serde(default)]
+    pub ignore_crates: Vec<String>,
+}
+
+impl AdvisoriesConfig {
+    pub fn is_ignored(&self, crate_name: &str, advisory_id: &str) -> bool {
+        self.ignore_crates.iter().any(|x| x == crate_name)
+            || self.ignore.iter().any(|x| x.id() == advisory_id)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+#[serde(untagged)]
+pub enum IgnoredAdvisory {
+    Id(String),
+    Table { id: String },
+}
+
+impl IgnoredAdvisory {
+    pub fn id(&self) -> &str {
+        match self {
+            IgnoredAdvisory::Id(id) => id,
+            IgnoredAdvisory::Table { id } => id,
+        }
+    }
+}
+
+#[test]
+fn test_repo_config_parse() {
+    let input = r#"
+        [advisories]
+        ignore = ["RUSTSEC-2020-0001", { id = "RUSTSEC-2020-0002", reason = "reviewed" }]
+        ignore-crates = ["foo"]
+
+        [bans]
+        multiple-versions = "deny"
+    "#;
+    let actual: RepoConfig = toml::from_str(input).unwrap();
+
+    assert!(actual.advisories.is_ignored("bar", "RUSTSEC-2020-0001"));
+    assert!(actual.advisories.is_ignored("bar", "RUSTSEC-2020-0002"));
+    assert!(actual.advisories.is_ignored("foo", "RUSTSEC-2020-0003"));
+    assert!(!actual.advisories.is_ignored("bar", "RUSTSEC-2020-0003"));
+}
+
+#[test]
+fn test_deny_config_parse() {
+    let input = r#"
+        [advisories]
+        ignore = [
+            "RUSTSEC-2020-0001",
+            { id = "RUSTSEC-2020-0002", reason = "reviewed" },
+            { crate = "foo", reason = "unmaintained" },
+        ]
+    "#;
+    let actual = RepoConfig::from_deny_toml(input.as_bytes());
+
+    assert_eq!(actual.advisories.ignore.len(), 2);
+    assert!(actual.advisories.is_ignored("bar", "RUSTSEC-2020-0002"));
+    assert_eq!(
+        RepoConfig::from_deny_toml(b"[advisories"),
+        RepoConfig::default()
+    );
+}
+
+#[test]
+fn test_policy_parse() {
+    let input = r#"
+        [policy]
+        kinds = ["normal", "build"]
+        ignore = ["foo"]
+        pinned = { tokio = "0.2" }
+    "#;
+    let policy = toml::from_str::<RepoConfig>(input).unwrap().policy;
+
+    assert!(policy.counts_kind(DependencyKind::Build));
+    assert!(!policy.counts_kind(DependencyKind::Dev));
+    assert!(policy.tolerates("foo", None));
+    assert!(policy.tolerates("tokio", Some(&"0.2.11".parse().unwrap())));
+    assert!(!policy.tolerates("tokio", Some(&"0.1.22".parse().unwrap())));
+    assert!(!policy.tolerates("bar", Some(&"0.2.11".parse().unwrap())));
+}
+
 #[test]
 fn test_simple_manifest_parse() {
     let input = include_str!("../fixture/manifest.toml");
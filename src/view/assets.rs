@@ -0,0 +1,39 @@
+use actix_web::{http::header, web::Path, HttpResponse};
+
+include!(concat!(env!("OUT_DIR"), "/static_files.rs"));
+
+/// Path of compiled style sheet, see `build.rs`.
+pub const STYLE_CSS: &str = concat!("/static/", env!("STYLE_CSS"));
+
+/// `Cache-Control` of files under `/static`, their names change along with content.
+const CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+/// Serve files embedded by `build.rs`.
+#[get("/static/{path:.*}")]
+pub async fn static_file(path: Path<String>) -> HttpResponse {
+    let content = match FILES.iter().find(|(name, _)| *name == path.as_str()) {
+        Some((_, content)) => *content,
+        None => return HttpResponse::NotFound().finish(),
+    };
+    HttpResponse::Ok()
+        .header(header::CONTENT_TYPE, content_type(&path))
+        .header(header::CACHE_CONTROL, CACHE_CONTROL)
+        .body(content)
+}
+
+fn content_type(path: &str) -> &'static str {
+    match path.rsplit('.').next() {
+        Some("css") => "text/css; charset=utf-8",
+        Some("png") => "image/png",
+        Some("svg") => "image/svg+xml",
+        Some("woff2") => "font/woff2",
+        Some("txt") => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    }
+}
+
+#[test]
+fn test_embedded_files() {
+    assert!(FILES.iter().any(|(name, _)| STYLE_CSS.ends_with(name)));
+    assert!(FILES.iter().all(|(_, content)| !content.is_empty()));
+}
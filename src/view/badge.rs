@@ -0,0 +1,239 @@
+use std::sync::Mutex;
+
+use badge::{Badge, BadgeOptions};
+use lru::LruCache;
+use once_cell::sync::Lazy;
+
+use crate::{database::DependencyKind, model::Status};
+
+const DEFAULT_LABEL: &str = "dependencies";
+const LABEL_LIMIT: usize = 64;
+
+/// Query parameters accepted by every `status.svg` route.
+#[derive(Debug, Deserialize, Default)]
+pub struct BadgeQuery {
+    #[serde(default)]
+    pub style: BadgeStyle,
+    pub label: Option<String>,
+    /// only tells "insecure" or "ok"
+    #[serde(default)]
+    pub compact: bool,
+    /// only count dependencies of this kind
+    pub kind: Option<DependencyKind>,
+}
+
+#[derive(Debug, Deserialize, Eq, PartialEq, Hash, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum BadgeStyle {
+    Flat,
+    FlatSquare,
+    ForTheBadge,
+    Plastic,
+}
+
+impl Default for BadgeStyle {
+    fn default() -> Self {
+        BadgeStyle::Flat
+    }
+}
+
+impl BadgeStyle {
+    pub fn name(self) -> &'static str {
+        match self {
+            BadgeStyle::Flat => "flat",
+            BadgeStyle::FlatSquare => "flat-square",
+            BadgeStyle::ForTheBadge => "for-the-badge",
+            BadgeStyle::Plastic => "plastic",
+        }
+    }
+}
+
+/// Response of shields.io endpoint badge, see https://shields.io/endpoint
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShieldsEndpoint {
+    schema_version: u8,
+    label: String,
+    message: String,
+    color: String,
+    style: &'static str,
+    is_error: bool,
+}
+
+#[derive(Debug, Eq, PartialEq, Hash, Clone)]
+struct RenderKey {
+    style: BadgeStyle,
+    label: String,
+    message: String,
+    color: &'static str,
+}
+
+impl BadgeQuery {
+    pub fn to_shields(&self, status: &Status) -> ShieldsEndpoint {
+        let key = self.render_key(status);
+        ShieldsEndpoint {
+            schema_version: 1,
+            label: key.label,
+            message: key.message,
+            color: key.color.trim_start_matches('#').to_string(),
+            style: key.style.name(),
+            is_error: match status {
+                Status::Insecure | Status::Normal { .. } => false,
+                _ => true,
+            },
+        }
+    }
+
+    pub fn render(&self, status: &Status) -> String {
+        static RENDERED: Lazy<Mutex<LruCache<RenderKey, String>>> =
+            Lazy::new(|| Mutex::new(LruCache::new(1024)));
+
+        let key = self.render_key(status);
+        if let Some(svg) = RENDERED.lock().unwrap().get(&key) {
+            return svg.clone();
+        }
+        let svg = render(&key);
+        RENDERED.lock().unwrap().put(key, svg.clone());
+        svg
+    }
+
+    fn render_key(&self, status: &Status) -> RenderKey {
+        let (message, color) = if self.compact {
+            status.compact_message()
+        } else {
+            status.message()
+        };
+        let label = self
+            .label
+            .as_deref()
+            .unwrap_or(DEFAULT_LABEL)
+            .chars()
+            .take(LABEL_LIMIT)
+            .collect::<String>();
+        RenderKey {
+            style: self.style,
+            label,
+            message,
+            color,
+        }
+    }
+}
+
+fn render(key: &RenderKey) -> String {
+    let label = escape(&key.label);
+    let message = escape(&key.message);
+    let lw = text_width(&key.label) + 10;
+    let rw = text_width(&key.message) + 10;
+    match key.style {
+        BadgeStyle::Flat => Badge::new(BadgeOptions {
+            subject: label,
+            status: message,
+            color: key.color.into(),
+        })
+        .unwrap()
+        .to_svg(),
+        BadgeStyle::FlatSquare => format!(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="20"><g shape-rendering="crispEdges"><rect width="{lw}" height="20" fill="#555"/><rect x="{lw}" width="{rw}" height="20" fill="{color}"/></g><g fill="#fff" text-anchor="middle" font-family="Verdana,Geneva,DejaVu Sans,sans-serif" font-size="11"><text x="{lx}" y="14">{label}</text><text x="{rx}" y="14">{message}</text></g></svg>"##,
+            w = lw + rw,
+            lw = lw,
+            rw = rw,
+            lx = lw / 2,
+            rx = lw + rw / 2,
+            color = key.color,
+            label = label,
+            message = message,
+        ),
+        BadgeStyle::Plastic => format!(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="18"><linearGradient id="s" x2="0" y2="100%"><stop offset="0" stop-color="#fff" stop-opacity=".7"/><stop offset=".1" stop-color="#aaa" stop-opacity=".1"/><stop offset=".9" stop-opacity=".3"/><stop offset="1" stop-opacity=".5"/></linearGradient><clipPath id="r"><rect width="{w}" height="18" rx="4" fill="#fff"/></clipPath><g clip-path="url(#r)"><rect width="{lw}" height="18" fill="#555"/><rect x="{lw}" width="{rw}" height="18" fill="{color}"/><rect width="{w}" height="18" fill="url(#s)"/></g><g fill="#fff" text-anchor="middle" font-family="Verdana,Geneva,DejaVu Sans,sans-serif" font-size="11"><text x="{lx}" y="14" fill="#010101" fill-opacity=".3">{label}</text><text x="{lx}" y="13">{label}</text><text x="{rx}" y="14" fill="#010101" fill-opacity=".3">{message}</text><text x="{rx}" y="13">{message}</text></g></svg>"##,
+            w = lw + rw,
+            lw = lw,
+            rw = rw,
+            lx = lw / 2,
+            rx = lw + rw / 2,
+            color = key.color,
+            label = label,
+            message = message,
+        ),
+        BadgeStyle::ForTheBadge => {
+            let label_upper = key.label.to_uppercase();
+            let message_upper = key.message.to_uppercase();
+            // bold capitals with letter spacing are about 1.3 times as wide
+            let lw = text_width(&label_upper) * 13 / 10 + 20;
+            let rw = text_width(&message_upper) * 13 / 10 + 20;
+            format!(
+                r##"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="28"><g shape-rendering="crispEdges"><rect width="{lw}" height="28" fill="#555"/><rect x="{lw}" width="{rw}" height="28" fill="{color}"/></g><g fill="#fff" text-anchor="middle" font-family="Verdana,Geneva,DejaVu Sans,sans-serif" font-size="10" font-weight="bold" letter-spacing="1"><text x="{lx}" y="18">{label}</text><text x="{rx}" y="18">{message}</text></g></svg>"##,
+                w = lw + rw,
+                lw = lw,
+                rw = rw,
+                lx = lw / 2,
+                rx = lw + rw / 2,
+                color = key.color,
+                label = escape(&label_upper),
+                message = escape(&message_upper),
+            )
+        }
+    }
+}
+
+/// Approximate rendered width of text in 11px Verdana.
+fn text_width(text: &str) -> usize {
+    text.chars()
+        .map(|c| match c {
+            'i' | 'j' | 'l' | '.' | ',' | ':' | ';' | '\'' | '|' | '!' => 3,
+            'f' | 'r' | 't' | ' ' | '(' | ')' | '[' | ']' | '-' | '/' => 5,
+            'm' | 'w' | 'M' | 'W' => 10,
+            'A'..='Z' | '0'..='9' => 7,
+            _ => 6,
+        })
+        .sum()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[test]
+fn test_render_key() {
+    let status = Status::Normal {
+        total: 3,
+        outdated: 0,
+        lag: crate::model::Lag::UpToDate,
+    };
+
+    let key = BadgeQuery::default().render_key(&status);
+    assert_eq!(key.label, DEFAULT_LABEL);
+    assert_eq!(key.message, "up to date");
+
+    let query = BadgeQuery {
+        label: Some("é".repeat(LABEL_LIMIT + 1)),
+        compact: true,
+        ..Default::default()
+    };
+    let key = query.render_key(&status);
+    assert_eq!(key.label, "é".repeat(LABEL_LIMIT));
+    assert_eq!(key.message, "ok");
+}
+
+#[test]
+fn test_render_escaped() {
+    for &style in &[
+        BadgeStyle::Flat,
+        BadgeStyle::FlatSquare,
+        BadgeStyle::ForTheBadge,
+        BadgeStyle::Plastic,
+    ] {
+        let query = BadgeQuery {
+            style,
+            label: Some(r#"<a href="x">&'"#.to_string()),
+            ..Default::default()
+        };
+        let svg = render(&query.render_key(&Status::Unknown));
+        assert!(!svg.to_lowercase().contains("<a href"), "{:?}", style);
+        assert!(svg.contains("&lt;"), "{:?}", style);
+        assert!(svg.contains("&amp;"), "{:?}", style);
+    }
+}
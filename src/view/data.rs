@@ -1,16 +1,33 @@
-use crate::analyze::AnalyzedDependency;
+use chrono::{DateTime, Utc};
+
+use semver::Version;
+
+use crate::{
+    analyze::AnalyzedDependency,
+    database::{AdvisoryRecord, CrateMeta},
+    model::Lag,
+    parser::Policy,
+};
 
 #[derive(Debug)]
 pub struct DepData {
     pub name: String,
     pub required: String,
     pub latest: String,
+    /// age of latest release, e.g. "3 days ago"
+    pub released: Option<String>,
+    pub stale: bool,
     pub outdated: bool,
+    /// outdated but accepted by repo policy
+    pub tolerated: bool,
+    pub lag: Lag,
     pub insecure: bool,
+    pub advisories: Vec<AdvisoryRecord>,
+    pub ignored_advisories: Vec<AdvisoryRecord>,
 }
 
-impl From<AnalyzedDependency> for DepData {
-    fn from(source: AnalyzedDependency) -> Self {
+impl DepData {
+    pub fn new(source: AnalyzedDependency, policy: &Policy) -> Self {
         Self {
             required: source.required.to_string(),
             latest: source
@@ -18,9 +35,100 @@ impl From<AnalyzedDependency> for DepData {
                 .as_ref()
                 .map(|x| x.to_string())
                 .unwrap_or_else(|| "N/A".to_string()),
-            outdated: source.is_outdated(),
+            released: source.latest_released_at.map(format_age),
+            stale: source.is_stale(),
+            outdated: source.is_outdated_under(policy),
+            tolerated: source.is_outdated() && !source.is_outdated_under(policy),
+            lag: source.lag(),
             insecure: source.is_insecure(),
             name: source.name,
+            advisories: source.advisories,
+            ignored_advisories: source.ignored_advisories,
         }
     }
 }
+
+#[derive(Debug)]
+pub struct VersionData {
+    pub version: String,
+    pub yanked: bool,
+    /// the version being shown
+    pub current: bool,
+}
+
+impl VersionData {
+    /// Every release of a crate, the newest first.
+    pub fn list(mut metas: Vec<CrateMeta>, current: &Version) -> Vec<Self> {
+        metas.sort_by(|a, b| b.vers.cmp(&a.vers));
+        metas
+            .into_iter()
+            .map(|x| Self {
+                current: &x.vers == current,
+                version: x.vers.to_string(),
+                yanked: x.yanked,
+            })
+            .collect()
+    }
+}
+
+/// Badge embedding code in common markup languages.
+#[derive(Debug)]
+pub struct BadgeSnippets {
+    pub markdown: String,
+    pub rst: String,
+    pub asciidoc: String,
+    pub html: String,
+}
+
+impl BadgeSnippets {
+    /// Badge at `badge_path` linking to `page_path`, both relative to public URL.
+    pub fn new(badge_path: &str, page_path: &str) -> Self {
+        let base = crate::command::public_url();
+        let badge = format!("{}/{}", base, badge_path);
+        let page = format!("{}/{}", base, page_path);
+        let alt = "dependency status";
+        BadgeSnippets {
+            markdown: format!("[![{}]({})]({})", alt, badge, page),
+            rst: format!(
+                ".. image:: {}\n   :alt: {}\n   :target: {}",
+                badge, alt, page
+            ),
+            asciidoc: format!("image:{}[{},link={}]", badge, alt, page),
+            html: format!(
+                r#"<a href="{}"><img src="{}" alt="{}"></a>"#,
+                page, badge, alt
+            ),
+        }
+    }
+}
+
+/// Human readable lines describing a non-default policy.
+pub fn policy_summary(policy: &Policy) -> Vec<String> {
+    let mut result = vec![];
+    if let Some(kinds) = &policy.kinds {
+        let kinds = kinds.iter().map(|x| x.name()).collect::<Vec<_>>();
+        result.push(format!("only {} dependencies counted", kinds.join(", ")));
+    }
+    if !policy.ignore.is_empty() {
+        result.push(format!("ignored crates: {}", policy.ignore.join(", ")));
+    }
+    for (name, req) in policy.pinned.iter() {
+        result.push(format!("{} pinned to {}", name, req));
+    }
+    result
+}
+
+fn format_age(time: DateTime<Utc>) -> String {
+    let days = (Utc::now() - time).num_days();
+    let (count, unit) = match days {
+        x if x < 1 => return "today".to_string(),
+        x if x < 30 => (x, "day"),
+        x if x < 365 => (x / 30, "month"),
+        x => (x / 365, "year"),
+    };
+    if count == 1 {
+        format!("1 {} ago", unit)
+    } else {
+        format!("{} {}s ago", count, unit)
+    }
+}
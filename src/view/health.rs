@@ -0,0 +1,81 @@
+use actix_web::HttpResponse;
+use chrono::Utc;
+
+use crate::database::RefreshState;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Readiness {
+    Ready,
+    /// serving, but data is not refreshed for too long
+    Degraded,
+    NotReady,
+}
+
+#[derive(Debug, Serialize)]
+struct Health {
+    status: Readiness,
+    index: IndexHealth,
+    advisory_db: AdvisoryHealth,
+}
+
+#[derive(Debug, Serialize)]
+struct IndexHealth {
+    tree_id: Option<String>,
+    crates: usize,
+    refresh: RefreshState,
+}
+
+#[derive(Debug, Serialize)]
+struct AdvisoryHealth {
+    advisories: Option<usize>,
+    refresh: RefreshState,
+}
+
+impl Health {
+    fn collect() -> Self {
+        let tree_id = crate::database::index_tree_id().map(|x| x.to_string());
+        let advisories = crate::database::advisory_count();
+        let refresh = crate::database::tick_state();
+
+        let status = if tree_id.is_none() || advisories.is_none() {
+            Readiness::NotReady
+        } else {
+            let degraded_after = chrono::Duration::from_std(crate::command::degraded_after())
+                .unwrap_or_else(|_| chrono::Duration::max_value());
+            match refresh.last_success {
+                Some(x) if Utc::now() - x <= degraded_after => Readiness::Ready,
+                _ => Readiness::Degraded,
+            }
+        };
+
+        Health {
+            status,
+            index: IndexHealth {
+                tree_id,
+                crates: crate::database::crate_count(),
+                refresh,
+            },
+            advisory_db: AdvisoryHealth {
+                advisories,
+                refresh: crate::database::audit_state(),
+            },
+        }
+    }
+}
+
+/// Liveness, succeeds as long as the server responds.
+#[get("/healthz")]
+pub async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().json(Health::collect())
+}
+
+/// Readiness, fails until both index and advisories are loaded.
+#[get("/readyz")]
+pub async fn readyz() -> HttpResponse {
+    let health = Health::collect();
+    match health.status {
+        Readiness::NotReady => HttpResponse::ServiceUnavailable().json(health),
+        Readiness::Ready | Readiness::Degraded => HttpResponse::Ok().json(health),
+    }
+}
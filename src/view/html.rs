@@ -1,28 +1,128 @@
-use actix_web::{http::header::ContentType, HttpResponse};
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    time::Duration,
+};
+
+use actix_web::{
+    http::{
+        header::{self, ContentType},
+        StatusCode,
+    },
+    HttpRequest, HttpResponse,
+};
 use askama::Template;
 
-use super::data::DepData;
+use crate::{
+    analyze::{AnalyzeError, AnalyzedCrate},
+    parser::Policy,
+};
+
+use super::data::{BadgeSnippets, DepData};
 
 #[derive(Template, Debug)]
 #[template(path = "server_error.html")]
 struct ServerErrorTemplate<'a> {
+    title: &'a str,
     message: &'a str,
 }
 
 pub fn server_error_response(e: impl AsRef<str>) -> HttpResponse {
-    let template = ServerErrorTemplate {
-        message: e.as_ref(),
-    };
+    error_page(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Server Error",
+        e.as_ref(),
+    )
+}
+
+pub fn not_found_response(e: impl AsRef<str>) -> HttpResponse {
+    error_page(StatusCode::NOT_FOUND, "Not Found", e.as_ref())
+}
+
+/// Error page telling why analysis failed, with a status code matching the cause.
+pub fn analyze_error_response(e: &AnalyzeError) -> HttpResponse {
+    let (status, title) = analyze_error_status(e);
+    error_page(status, title, &e.to_string())
+}
+
+pub fn analyze_error_status(e: &AnalyzeError) -> (StatusCode, &'static str) {
+    match e {
+        AnalyzeError::NotFound(_) => (StatusCode::NOT_FOUND, "Not Found"),
+        AnalyzeError::Invalid(_) => (StatusCode::BAD_REQUEST, "Bad Request"),
+        AnalyzeError::Parse { .. } => (StatusCode::UNPROCESSABLE_ENTITY, "Invalid Manifest"),
+        AnalyzeError::Upstream(_) => (StatusCode::BAD_GATEWAY, "Upstream Error"),
+        AnalyzeError::Timeout => (StatusCode::GATEWAY_TIMEOUT, "Timeout"),
+        AnalyzeError::Internal(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Server Error"),
+    }
+}
+
+fn error_page(status: StatusCode, title: &str, message: &str) -> HttpResponse {
+    let template = ServerErrorTemplate { title, message };
 
-    HttpResponse::InternalServerError()
+    HttpResponse::build(status)
         .set(ContentType::html())
         .body(template.render().unwrap())
 }
 
-pub fn render_template(template: impl Template) -> HttpResponse {
+pub fn render_template(req: &HttpRequest, template: impl Template) -> HttpResponse {
+    cacheable_response(req, "text/html; charset=utf-8", template.render().unwrap())
+}
+
+pub fn svg_response(req: &HttpRequest, svg: String) -> HttpResponse {
+    cacheable_response(req, "image/svg+xml;charset=utf-8", svg)
+}
+
+pub fn json_response(req: &HttpRequest, value: &impl serde::Serialize) -> HttpResponse {
+    match serde_json::to_string(value) {
+        Ok(body) => cacheable_response(req, "application/json", body),
+        Err(e) => {
+            error!("failed to serialize response: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Response with `ETag` and `Cache-Control`, or `304 Not Modified` if the client has it already.
+///
+/// `ETag` is derived from the body and the loaded index tree, so it changes once either changes.
+pub fn cacheable_response(req: &HttpRequest, content_type: &str, body: String) -> HttpResponse {
+    let mut hasher = DefaultHasher::new();
+    crate::database::index_tree_id().hash(&mut hasher);
+    body.hash(&mut hasher);
+    let etag = format!("\"{:016x}\"", hasher.finish());
+    tagged_response(req, content_type, body, etag, crate::command::max_age())
+}
+
+fn tagged_response(
+    req: &HttpRequest,
+    content_type: &str,
+    body: String,
+    etag: String,
+    max_age: Duration,
+) -> HttpResponse {
+    let cache_control = format!("public, max-age={}", max_age.as_secs());
+
+    let matched = req
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|x| x.to_str().ok())
+        .map_or(false, |x| {
+            x.split(',')
+                .map(|x| x.trim().trim_start_matches("W/"))
+                .any(|x| x == etag || x == "*")
+        });
+    if matched {
+        return HttpResponse::NotModified()
+            .header(header::ETAG, etag)
+            .header(header::CACHE_CONTROL, cache_control)
+            .finish();
+    }
+
     HttpResponse::Ok()
-        .set(ContentType::html())
-        .body(template.render().unwrap())
+        .content_type(content_type)
+        .header(header::ETAG, etag)
+        .header(header::CACHE_CONTROL, cache_control)
+        .body(body)
 }
 
 #[derive(Debug, Template)]
@@ -35,7 +135,10 @@ pub struct DependenciesTableTemplate {
 }
 
 impl DependenciesTableTemplate {
-    pub fn new(deps: Vec<DepData>) -> Self {
+    /// Sort given dependencies by severity, the most severe first.
+    pub fn new(mut deps: Vec<DepData>) -> Self {
+        deps.sort_by(|a, b| (b.insecure, b.outdated, b.lag).cmp(&(a.insecure, a.outdated, a.lag)));
+
         let count_outdated = deps.iter().filter(|x| x.outdated).count();
         let count_insecure = deps.iter().filter(|x| x.insecure).count();
         let count_total = deps.len();
@@ -49,10 +152,93 @@ impl DependenciesTableTemplate {
 }
 
 #[derive(Template, Debug)]
-#[template(path = "crate_section.html", escape = "none")]
+#[template(path = "crate_section.html")]
 pub struct CrateSectionTemplate {
     pub name: String,
+    /// badge for this crate alone, for workspace members only
+    pub member_badge: Option<BadgeSnippets>,
     pub dependencies: DependenciesTableTemplate,
     pub build_dependencies: DependenciesTableTemplate,
     pub dev_dependencies: DependenciesTableTemplate,
 }
+
+impl CrateSectionTemplate {
+    pub fn new(source: AnalyzedCrate, policy: &Policy) -> Self {
+        let to_table = |deps: Vec<_>| {
+            DependenciesTableTemplate::new(
+                deps.into_iter().map(|d| DepData::new(d, policy)).collect(),
+            )
+        };
+        Self {
+            name: source.name,
+            member_badge: None,
+            dependencies: to_table(source.dependencies),
+            build_dependencies: to_table(source.build_dependencies),
+            dev_dependencies: to_table(source.dev_dependencies),
+        }
+    }
+}
+
+#[test]
+fn test_crate_section_snippets_escaped() {
+    let section = CrateSectionTemplate {
+        name: "foo".to_string(),
+        member_badge: Some(BadgeSnippets {
+            markdown: String::new(),
+            rst: String::new(),
+            asciidoc: String::new(),
+            html: r#"<a href="page"><img src="badge"></a>"#.to_string(),
+        }),
+        dependencies: DependenciesTableTemplate::new(vec![]),
+        build_dependencies: DependenciesTableTemplate::new(vec![]),
+        dev_dependencies: DependenciesTableTemplate::new(vec![]),
+    };
+    let html = section.render().unwrap();
+    assert!(!html.contains("<img src"));
+    assert!(html.contains("&lt;img src="));
+}
+
+#[test]
+fn test_tagged_response() {
+    use actix_web::test::TestRequest;
+
+    let respond = |if_none_match: Option<&str>| {
+        let mut req = TestRequest::default();
+        if let Some(x) = if_none_match {
+            req = req.header(header::IF_NONE_MATCH, x);
+        }
+        tagged_response(
+            &req.to_http_request(),
+            "application/json",
+            "{}".to_string(),
+            "\"abc\"".to_string(),
+            Duration::from_secs(60),
+        )
+    };
+
+    for if_none_match in &[
+        "\"abc\"",
+        "W/\"abc\"",
+        "\"xyz\", \"abc\"",
+        "W/\"xyz\",W/\"abc\"",
+        "*",
+    ] {
+        let resp = respond(Some(*if_none_match));
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED, "{}", if_none_match);
+        assert_eq!(resp.headers().get(header::ETAG).unwrap(), "\"abc\"");
+        assert_eq!(
+            resp.headers().get(header::CACHE_CONTROL).unwrap(),
+            "public, max-age=60"
+        );
+    }
+
+    for if_none_match in &[None, Some("\"xyz\""), Some("\"abcd\""), Some("W/\"xyz\"")] {
+        let resp = respond(*if_none_match);
+        assert_eq!(resp.status(), StatusCode::OK, "{:?}", if_none_match);
+        assert_eq!(resp.headers().get(header::ETAG).unwrap(), "\"abc\"");
+        assert_eq!(
+            resp.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/json"
+        );
+    }
+}
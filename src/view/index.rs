@@ -0,0 +1,111 @@
+use actix_web::{http::header, web::Query, HttpRequest, HttpResponse};
+use askama::Template;
+
+use crate::{
+    history::HistoryEntry,
+    model::{RepoIdentity, Site},
+    view::html::{not_found_response, render_template, server_error_response},
+};
+
+const LIST_LIMIT: usize = 10;
+
+#[derive(Template, Debug)]
+#[template(path = "index.html")]
+struct IndexTemplate {
+    recent: Vec<HistoryEntry>,
+    popular: Vec<HistoryEntry>,
+}
+
+#[get("/")]
+pub async fn index(req: HttpRequest) -> HttpResponse {
+    let history = crate::analyze::blocking(|| crate::history::recent_and_popular(LIST_LIMIT)).await;
+    let (recent, popular) = match history {
+        Ok(Ok(x)) => x,
+        Ok(Err(e)) => {
+            error!("failed to load history: {:?}", e);
+            return server_error_response("failed to load history");
+        }
+        Err(e) => {
+            error!("failed to load history: {}", e);
+            return server_error_response("failed to load history");
+        }
+    };
+
+    render_template(&req, IndexTemplate { recent, popular })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    q: String,
+}
+
+/// Redirect to page of given repo URL or crate name.
+#[get("/search")]
+pub async fn search(query: Query<SearchQuery>) -> HttpResponse {
+    let location = search_target(query.q.trim()).unwrap_or_else(|| "/".to_string());
+    HttpResponse::Found()
+        .header(header::LOCATION, location)
+        .finish()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GoQuery {
+    url: String,
+}
+
+/// Redirect to page of given repository URL.
+#[get("/go")]
+pub async fn go(query: Query<GoQuery>) -> HttpResponse {
+    match RepoIdentity::parse_url(&query.url) {
+        Some(ident) => HttpResponse::Found()
+            .header(
+                header::LOCATION,
+                format!("/{}{}", ident.path(), ident.query()),
+            )
+            .finish(),
+        None => not_found_response(format!("unrecognized repository URL: {}", query.url)),
+    }
+}
+
+fn search_target(input: &str) -> Option<String> {
+    if let Some(ident) = RepoIdentity::parse_url(input) {
+        return Some(format!("/{}{}", ident.path(), ident.query()));
+    }
+
+    let is_name = |x: &str| {
+        !x.is_empty()
+            && x.chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+    };
+
+    match input.splitn(2, '/').collect::<Vec<_>>().as_slice() {
+        [owner, repo] if is_name(owner) && is_name(repo) => {
+            Some(format!("/repo/{}/{}/{}", Site::GitHub.name(), owner, repo))
+        }
+        [name] if is_name(name) => Some(format!("/crate/{}", name)),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_search_target() {
+    assert_eq!(
+        search_target("https://github.com/DCjanus/deps-rs"),
+        Some("/repo/github/DCjanus/deps-rs".to_string())
+    );
+    assert_eq!(
+        search_target("gitlab.com/owner/repo.git"),
+        Some("/repo/gitlab/owner/repo".to_string())
+    );
+    assert_eq!(
+        search_target("serde-rs/serde"),
+        Some("/repo/github/serde-rs/serde".to_string())
+    );
+    assert_eq!(
+        search_target("https://gitlab.com/group/subgroup/project/-/tree/dev"),
+        Some("/repo/gitlab/group%2Fsubgroup/project?ref=dev".to_string())
+    );
+    assert_eq!(search_target("serde"), Some("/crate/serde".to_string()));
+    assert_eq!(search_target("no such crate"), None);
+    assert_eq!(search_target(""), None);
+}
@@ -1,156 +1,413 @@
-use actix_web::{HttpResponse, Responder};
+use actix_web::{web::Query, HttpRequest, HttpResponse, Responder};
 use askama::Template;
 
 use crate::{
-    analyze::AnalyzedCrate,
-    model::{CrateIdentity, RepoIdentity, Status},
-    view::html::{render_template, server_error_response},
+    analyze::{AnalyzeError, FailedMember},
+    database::DependencyKind,
+    model::{CrateIdentity, CrateSpec, RepoIdentity, RepoLocation, RepoMemberIdentity, Status},
+    parser::Policy,
+    view::{
+        badge::BadgeQuery,
+        data::{policy_summary, BadgeSnippets, VersionData},
+        html::{
+            analyze_error_response, analyze_error_status, json_response, not_found_response,
+            render_template, svg_response,
+        },
+    },
 };
 
-use super::html::{CrateSectionTemplate, DependenciesTableTemplate};
+use super::html::CrateSectionTemplate;
+
+async fn repo_status(ident: &RepoIdentity, kind: Option<DependencyKind>) -> Status {
+    match crate::analyze::analyze_repo(ident).await {
+        Ok(x) => match kind {
+            None => {
+                let status = x.status();
+                record_repo(ident, &status);
+                status
+            }
+            Some(kind) => x.only(kind).status(),
+        },
+        Err(e) => {
+            error!("{:?}", e);
+            e.status()
+        }
+    }
+}
+
+fn record_repo(ident: &RepoIdentity, status: &Status) {
+    crate::history::record(
+        format!("{}{}", ident.path(), ident.query()),
+        ident.title(),
+        status,
+    );
+}
+
+/// Repo at ref and path given in query, rejected if those could escape the repo.
+fn locate(ident: RepoIdentity, location: &RepoLocation) -> Result<RepoIdentity, AnalyzeError> {
+    ident
+        .locate(location)
+        .ok_or_else(|| AnalyzeError::Invalid("invalid ref or path".to_string()))
+}
+
+/// Color of the page header for `status`.
+fn hero_class(status: &Status) -> &'static str {
+    match status {
+        Status::Unknown | Status::NotFound | Status::Error => "is-dark",
+        Status::Insecure => "is-danger",
+        Status::Normal { outdated, .. } => {
+            if *outdated > 0 {
+                "is-warning"
+            } else {
+                "is-success"
+            }
+        }
+    }
+}
+
+async fn crate_status(spec: &CrateSpec, kind: Option<DependencyKind>) -> Status {
+    match crate::analyze::analyze_crate_spec(spec).await {
+        Ok(None) => Status::NotFound,
+        Ok(Some((_, x))) => match kind {
+            None => x.status(&Policy::default()),
+            Some(kind) => x.only(kind).status(&Policy::default()),
+        },
+        Err(e) => {
+            error!("{:?}", e);
+            e.status()
+        }
+    }
+}
 
 #[get("/repo/{site}/{owner}/{repo}/status.svg")]
-pub async fn repo_svg(input: actix_web::web::Path<RepoIdentity>) -> impl Responder {
-    let status = match crate::analyze::analyze_repo(input.as_ref()).await {
-        Ok(x) => x.into_iter().map(|x| x.status()).sum::<Status>(),
+pub async fn repo_svg(
+    req: HttpRequest,
+    input: actix_web::web::Path<RepoIdentity>,
+    location: Query<RepoLocation>,
+    query: Query<BadgeQuery>,
+) -> impl Responder {
+    let status = match locate(input.into_inner(), &location) {
+        Ok(ident) => repo_status(&ident, query.kind).await,
+        Err(e) => e.status(),
+    };
+    svg_response(&req, query.render(&status))
+}
+
+#[get("/repo/{site}/{owner}/{repo}/crate/{member}/status.svg")]
+pub async fn repo_member_svg(
+    req: HttpRequest,
+    input: actix_web::web::Path<RepoMemberIdentity>,
+    location: Query<RepoLocation>,
+    query: Query<BadgeQuery>,
+) -> impl Responder {
+    let result = match locate(input.repo(), &location) {
+        Ok(ident) => crate::analyze::analyze_repo(&ident).await,
+        Err(e) => Err(e),
+    };
+    let status = match result {
+        Ok(x) => match x.crates.iter().find(|c| c.name == input.member) {
+            None => Status::NotFound,
+            Some(c) => match query.kind {
+                None => c.status(&x.config.policy),
+                Some(kind) => c.only(kind).status(&x.config.policy),
+            },
+        },
         Err(e) => {
             error!("{:?}", e);
-            Status::Unknown
+            e.status()
         }
     };
-    HttpResponse::Ok()
-        .content_type("image/svg+xml;charset=utf-8")
-        .body(status.to_badge().to_svg())
+    svg_response(&req, query.render(&status))
+}
+
+#[get("/repo/{site}/{owner}/{repo}/shields.json")]
+pub async fn repo_shields(
+    req: HttpRequest,
+    input: actix_web::web::Path<RepoIdentity>,
+    location: Query<RepoLocation>,
+    query: Query<BadgeQuery>,
+) -> HttpResponse {
+    let status = match locate(input.into_inner(), &location) {
+        Ok(ident) => repo_status(&ident, query.kind).await,
+        Err(e) => e.status(),
+    };
+    json_response(&req, &query.to_shields(&status))
+}
+
+#[get("/repo/{site}/{owner}/{repo}/status.json")]
+pub async fn repo_json(
+    req: HttpRequest,
+    input: actix_web::web::Path<RepoIdentity>,
+    location: Query<RepoLocation>,
+) -> HttpResponse {
+    let result = match locate(input.into_inner(), &location) {
+        Ok(ident) => crate::analyze::analyze_repo(&ident).await,
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(x) => json_response(&req, &x),
+        Err(e) => {
+            error!("{:?}", e);
+            let (status, _) = analyze_error_status(&e);
+            HttpResponse::build(status).json(serde_json::json!({ "error": e.to_string() }))
+        }
+    }
 }
 
 #[derive(Template, Debug)]
-#[template(path = "repo_status.html", escape = "none")]
+#[template(path = "repo_status.html")]
 struct RepoHtmlTemplate<'a> {
     hero_class: &'static str,
     ident: &'a RepoIdentity,
+    snippets: BadgeSnippets,
     status: &'a Status,
+    policy: Vec<String>,
     crates: Vec<CrateSectionTemplate>,
+    failed_members: Vec<FailedMember>,
 }
 
 #[get("/repo/{site}/{owner}/{repo}")]
-pub async fn repo_html(input: actix_web::web::Path<RepoIdentity>) -> HttpResponse {
-    let analyze_result = match crate::analyze::analyze_repo(input.as_ref()).await {
+pub async fn repo_html(
+    req: HttpRequest,
+    input: actix_web::web::Path<RepoIdentity>,
+    location: Query<RepoLocation>,
+) -> HttpResponse {
+    let ident = match locate(input.into_inner(), &location) {
+        Ok(x) => x,
+        Err(e) => return analyze_error_response(&e),
+    };
+    let analyze_result = match crate::analyze::analyze_repo(&ident).await {
         Ok(x) => x,
         Err(e) => {
             error!("{:?}", e);
-            return server_error_response("failed to analyze given repo");
+            return analyze_error_response(&e);
         }
     };
 
-    let status = analyze_result.iter().map(|x| x.status()).sum::<Status>();
-    let hero_class = match status {
-        Status::Unknown => unreachable!(),
-        Status::Insecure => "is-danger",
-        Status::Normal { outdated, .. } => {
-            if outdated > 0 {
-                "is-warning"
-            } else {
-                "is-success"
-            }
-        }
-    };
+    let status = analyze_result.status();
+    record_repo(&ident, &status);
+    let hero_class = hero_class(&status);
 
+    let policy = analyze_result.config.policy;
+    let is_workspace = analyze_result.crates.len() > 1;
+    let repo_path = ident.path();
+    let repo_query = ident.query();
     let crates: Vec<CrateSectionTemplate> = analyze_result
+        .crates
         .into_iter()
-        .map(|x: AnalyzedCrate| CrateSectionTemplate {
-            name: x.name,
-            dependencies: DependenciesTableTemplate::new(
-                x.dependencies.into_iter().map(|d| d.into()).collect(),
-            ),
-            build_dependencies: DependenciesTableTemplate::new(
-                x.build_dependencies.into_iter().map(|d| d.into()).collect(),
-            ),
-            dev_dependencies: DependenciesTableTemplate::new(
-                x.dev_dependencies.into_iter().map(|d| d.into()).collect(),
-            ),
+        .map(|x| {
+            let mut section = CrateSectionTemplate::new(x, &policy);
+            if is_workspace {
+                section.member_badge = Some(BadgeSnippets::new(
+                    &format!(
+                        "{}/crate/{}/status.svg{}",
+                        repo_path, section.name, repo_query
+                    ),
+                    &format!("{}{}#crate-{}", repo_path, repo_query, section.name),
+                ));
+            }
+            section
         })
         .collect();
 
-    render_template(RepoHtmlTemplate {
-        hero_class,
-        ident: &input,
-        status: &status,
-        crates,
-    })
+    render_template(
+        &req,
+        RepoHtmlTemplate {
+            hero_class,
+            snippets: BadgeSnippets::new(
+                &format!("{}/status.svg{}", repo_path, repo_query),
+                &format!("{}{}", repo_path, repo_query),
+            ),
+            ident: &ident,
+            status: &status,
+            policy: policy_summary(&policy),
+            crates,
+            failed_members: analyze_result.failed_members,
+        },
+    )
+}
+
+#[get("/crate/{name}/status.svg")]
+pub async fn crate_latest_svg(
+    req: HttpRequest,
+    input: actix_web::web::Path<CrateSpec>,
+    query: Query<BadgeQuery>,
+) -> impl Responder {
+    crate_svg_response(&req, &input, &query).await
 }
 
 #[get("/crate/{name}/{version}/status.svg")]
-pub async fn crate_svg(input: actix_web::web::Path<CrateIdentity>) -> impl Responder {
-    let status = match crate::analyze::analyze_crate(&input.name, input.version.clone()) {
-        None => Status::Unknown,
-        Some(x) => x.status(),
-    };
-    HttpResponse::Ok()
-        .content_type("image/svg+xml;charset=utf-8")
-        .body(status.to_badge().to_svg())
+pub async fn crate_svg(
+    req: HttpRequest,
+    input: actix_web::web::Path<CrateSpec>,
+    query: Query<BadgeQuery>,
+) -> impl Responder {
+    crate_svg_response(&req, &input, &query).await
+}
+
+async fn crate_svg_response(
+    req: &HttpRequest,
+    spec: &CrateSpec,
+    query: &BadgeQuery,
+) -> HttpResponse {
+    let status = crate_status(spec, query.kind).await;
+    svg_response(req, query.render(&status))
+}
+
+#[get("/crate/{name}/shields.json")]
+pub async fn crate_latest_shields(
+    req: HttpRequest,
+    input: actix_web::web::Path<CrateSpec>,
+    query: Query<BadgeQuery>,
+) -> HttpResponse {
+    crate_shields_response(&req, &input, &query).await
+}
+
+#[get("/crate/{name}/{version}/shields.json")]
+pub async fn crate_shields(
+    req: HttpRequest,
+    input: actix_web::web::Path<CrateSpec>,
+    query: Query<BadgeQuery>,
+) -> HttpResponse {
+    crate_shields_response(&req, &input, &query).await
+}
+
+async fn crate_shields_response(
+    req: &HttpRequest,
+    spec: &CrateSpec,
+    query: &BadgeQuery,
+) -> HttpResponse {
+    let status = crate_status(spec, query.kind).await;
+    json_response(req, &query.to_shields(&status))
+}
+
+#[get("/crate/{name}/status.json")]
+pub async fn crate_latest_json(
+    req: HttpRequest,
+    input: actix_web::web::Path<CrateSpec>,
+) -> HttpResponse {
+    crate_json_response(&req, &input).await
+}
+
+#[get("/crate/{name}/{version}/status.json")]
+pub async fn crate_json(req: HttpRequest, input: actix_web::web::Path<CrateSpec>) -> HttpResponse {
+    crate_json_response(&req, &input).await
+}
+
+async fn crate_json_response(req: &HttpRequest, spec: &CrateSpec) -> HttpResponse {
+    match crate::analyze::analyze_crate_spec(spec).await {
+        Ok(Some((_, x))) => json_response(req, &x),
+        Ok(None) => HttpResponse::NotFound()
+            .json(serde_json::json!({ "error": "no such crate or version" })),
+        Err(e) => {
+            error!("{:?}", e);
+            let (status, _) = analyze_error_status(&e);
+            HttpResponse::build(status).json(serde_json::json!({ "error": e.to_string() }))
+        }
+    }
 }
 
 #[derive(Debug, Template)]
-#[template(path = "crate_status.html", escape = "none")]
+#[template(path = "crate_status.html")]
 struct CrateHtmlTemplate<'a> {
     hero_class: &'static str,
     ident: &'a CrateIdentity,
+    snippets: BadgeSnippets,
     status: Status,
+    versions: Vec<VersionData>,
     the_crate: CrateSectionTemplate,
 }
 
+#[get("/crate/{name}")]
+pub async fn crate_latest_html(
+    req: HttpRequest,
+    input: actix_web::web::Path<CrateSpec>,
+) -> HttpResponse {
+    crate_html_response(&req, &input).await
+}
+
 #[get("/crate/{name}/{version}")]
-pub fn crate_html(input: actix_web::web::Path<CrateIdentity>) -> HttpResponse {
-    let analyze_result = match crate::analyze::analyze_crate(&input.name, input.version.clone()) {
-        Some(x) => x,
-        None => {
-            error!("failed to analyze crate: {} {}", input.name, input.version);
-            return server_error_response("failed to analyze given crate");
+pub async fn crate_html(req: HttpRequest, input: actix_web::web::Path<CrateSpec>) -> HttpResponse {
+    crate_html_response(&req, &input).await
+}
+
+async fn crate_html_response(req: &HttpRequest, spec: &CrateSpec) -> HttpResponse {
+    let (ident, analyze_result) = match crate::analyze::analyze_crate_spec(spec).await {
+        Ok(Some(x)) => x,
+        Ok(None) => {
+            debug!("failed to resolve or analyze crate: {:?}", spec);
+            return not_found_response("no such crate or version");
+        }
+        Err(e) => {
+            error!("{:?}", e);
+            return analyze_error_response(&e);
         }
     };
 
-    let status = analyze_result.status();
-    let hero_class = match status {
-        Status::Unknown => unreachable!(),
-        Status::Insecure => "is-danger",
-        Status::Normal { outdated, .. } => {
-            if outdated > 0 {
-                "is-warning"
-            } else {
-                "is-success"
-            }
+    let policy = Policy::default();
+    let status = analyze_result.status(&policy);
+    crate::history::record(format!("crate/{}", ident.name), ident.name.clone(), &status);
+    let hero_class = hero_class(&status);
+
+    let name = ident.name.clone();
+    let metas = crate::analyze::blocking(move || crate::database::get_crate_metas(&name)).await;
+    let versions = match metas {
+        Ok(Ok(Some(metas))) => VersionData::list(metas, &ident.version),
+        Ok(Ok(None)) => vec![],
+        Ok(Err(error)) => {
+            error!("failed to get crate metadata: {:?}", error);
+            vec![]
+        }
+        Err(error) => {
+            error!("failed to get crate metadata: {:?}", error);
+            vec![]
         }
     };
 
-    let the_crate = CrateSectionTemplate {
-        name: analyze_result.name,
-        dependencies: DependenciesTableTemplate::new(
-            analyze_result
-                .dependencies
-                .into_iter()
-                .map(|d| d.into())
-                .collect(),
-        ),
-        build_dependencies: DependenciesTableTemplate::new(
-            analyze_result
-                .build_dependencies
-                .into_iter()
-                .map(|d| d.into())
-                .collect(),
-        ),
-        dev_dependencies: DependenciesTableTemplate::new(
-            analyze_result
-                .dev_dependencies
-                .into_iter()
-                .map(|d| d.into())
-                .collect(),
-        ),
-    };
+    let the_crate = CrateSectionTemplate::new(analyze_result, &policy);
 
-    render_template(CrateHtmlTemplate {
-        hero_class,
-        ident: &input,
-        status,
-        the_crate,
-    })
+    render_template(
+        req,
+        CrateHtmlTemplate {
+            hero_class,
+            ident: &ident,
+            snippets: BadgeSnippets::new(&format!("{}/status.svg", spec.path()), &spec.path()),
+            status,
+            versions,
+            the_crate,
+        },
+    )
+}
+
+#[test]
+fn test_repo_html_escaped() {
+    let ident = RepoIdentity {
+        site: crate::model::Site::GitHub,
+        owner: "owner".to_string(),
+        repo: "repo".to_string(),
+        git_ref: None,
+        path: None,
+    };
+    let template = RepoHtmlTemplate {
+        hero_class: "is-dark",
+        ident: &ident,
+        snippets: BadgeSnippets {
+            markdown: String::new(),
+            rst: String::new(),
+            asciidoc: String::new(),
+            html: String::new(),
+        },
+        status: &Status::Unknown,
+        policy: vec!["<svg onload=alert(1)> pinned to 0.1".to_string()],
+        crates: vec![],
+        failed_members: vec![FailedMember {
+            path: "<script>alert(1)</script>".to_string(),
+            error: "<img src=x onerror=alert(1)>".to_string(),
+        }],
+    };
+    let html = template.render().unwrap();
+    assert!(!html.contains("<script>alert"));
+    assert!(!html.contains("<img src=x"));
+    assert!(!html.contains("<svg onload"));
+    assert!(html.contains("&lt;script&gt;"));
 }